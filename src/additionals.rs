@@ -97,6 +97,7 @@ impl AdditionalEvent {
             description: DESCRIPTION.to_owned(),
             start,
             end,
+            uid: String::new(),
         })
     }
 }
@@ -0,0 +1,398 @@
+//! Runtime configuration loaded from `config.toml`
+
+use std::collections::HashSet;
+use std::{env, fs};
+
+use anyhow::Context as _;
+use lazy_regex::{Regex, regex_is_match};
+use serde::Deserialize;
+use url::Url;
+
+use crate::events_git;
+use crate::file_stem::{self, FileStemStrategy};
+use crate::metadata;
+use crate::sanity::Sanity;
+
+/// Used when there is no config file to load
+const SHIPPED: &str = include_str!("../config.toml");
+const DEFAULT_FILE: &str = "config.toml";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub git: Git,
+    #[serde(rename = "source")]
+    pub sources: Vec<Source>,
+}
+
+/// How the events repo is accessed and published
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Git {
+    #[serde(default)]
+    pub backend: GitBackend,
+    #[serde(default = "default_remote")]
+    pub remote: String,
+    /// Branch to clone instead of the default branch of the remote.
+    /// Pulls and pushes follow the upstream of the checked out branch.
+    pub branch: Option<String>,
+    #[serde(default = "default_author_name")]
+    pub author_name: String,
+    #[serde(default = "default_author_email")]
+    pub author_email: String,
+    /// Whether commits are pushed. Only release builds push by default.
+    #[serde(default = "push_by_default")]
+    pub push: bool,
+    /// Amount of commits to clone. 0 clones the full history.
+    #[serde(default = "default_depth")]
+    pub depth: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitBackend {
+    /// Run the `git` binary
+    #[default]
+    Cli,
+    /// Pure-Rust implementation based on gitoxide
+    Gix,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Source {
+    pub base: String,
+    #[serde(default)]
+    pub kind: Kind,
+    pub urls: Vec<Url>,
+    pub login_url: Option<Url>,
+    pub link_pattern: Option<LinkPattern>,
+    pub crawl: Option<Crawl>,
+    /// Defaults depend on the kind, see [`Source::encoding`]
+    encoding: Option<Encoding>,
+    #[serde(
+        default = "file_stem::default",
+        deserialize_with = "file_stem::deserialize"
+    )]
+    pub file_stem: Box<dyn FileStemStrategy>,
+    #[serde(default)]
+    pub sanity: Sanity,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Public pages linking to ICS files
+    #[default]
+    Index,
+    /// Export pages behind the myHAW login
+    MyHaw,
+    /// `file://` urls of ICS files or directories containing ICS files
+    Local,
+    /// `CalDAV` collection which is mirrored locally
+    CalDav,
+}
+
+/// Follow links of index pages to find ICS files on subpages
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Crawl {
+    /// How many levels of subpages are visited
+    pub depth: usize,
+    /// Regex the subpage urls have to match. Only pages of the same domain are visited.
+    pub page_pattern: Option<LinkPattern>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct LinkPattern(Regex);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Also known as ISO-8859-1 but that doesnt seem to be a defined standard.
+    /// <https://docs.rs/encoding_rs/0.8.35/encoding_rs/index.html#iso-8859-1>
+    Latin1,
+    /// UTF-8 when the bytes are valid UTF-8, Latin1 otherwise
+    Utf8OrLatin1,
+    Other(&'static encoding_rs::Encoding),
+}
+
+const fn enabled_by_default() -> bool {
+    true
+}
+
+fn default_remote() -> String {
+    events_git::REMOTE.to_owned()
+}
+
+fn default_author_name() -> String {
+    events_git::AUTHOR_NAME.to_owned()
+}
+
+fn default_author_email() -> String {
+    events_git::AUTHOR_EMAIL.to_owned()
+}
+
+const fn push_by_default() -> bool {
+    !cfg!(debug_assertions)
+}
+
+const fn default_depth() -> u32 {
+    1
+}
+
+impl Default for Git {
+    fn default() -> Self {
+        Self {
+            backend: GitBackend::default(),
+            remote: default_remote(),
+            branch: None,
+            author_name: default_author_name(),
+            author_email: default_author_email(),
+            push: push_by_default(),
+            depth: default_depth(),
+        }
+    }
+}
+
+/// Load the config file from `CONFIG_FILE`, `config.toml` or the shipped default
+pub fn load() -> anyhow::Result<Config> {
+    let config = if let Ok(path) = env::var("CONFIG_FILE") {
+        parse(&fs::read_to_string(&path).with_context(|| format!("read config {path}"))?)
+            .with_context(|| format!("config {path}"))?
+    } else if let Ok(content) = fs::read_to_string(DEFAULT_FILE) {
+        parse(&content).with_context(|| format!("config {DEFAULT_FILE}"))?
+    } else {
+        parse(SHIPPED).context("shipped config")?
+    };
+    Ok(config)
+}
+
+fn parse(content: &str) -> anyhow::Result<Config> {
+    let config: Config = toml::from_str(content)?;
+    config.validate()?;
+    Ok(config)
+}
+
+impl Config {
+    fn validate(&self) -> anyhow::Result<()> {
+        self.git.validate().context("git")?;
+        let mut bases = HashSet::new();
+        for source in &self.sources {
+            let base = &source.base;
+            anyhow::ensure!(
+                regex_is_match!("^[a-z0-9][a-z0-9_-]*$", base),
+                "base {base:?} should only contain lowercase letters, digits, - and _"
+            );
+            anyhow::ensure!(
+                base != metadata::FOLDER,
+                "base {base} is reserved for the event metadata"
+            );
+            anyhow::ensure!(bases.insert(base), "base {base} is configured twice");
+            source.validate().with_context(|| format!("base {base}"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Git {
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("remote", &self.remote),
+            ("author-name", &self.author_name),
+            ("author-email", &self.author_email),
+        ] {
+            anyhow::ensure!(!value.trim().is_empty(), "{name} should not be empty");
+        }
+        if let Some(branch) = &self.branch {
+            anyhow::ensure!(
+                regex_is_match!("^[A-Za-z0-9][A-Za-z0-9._/-]*$", branch),
+                "branch {branch:?} is not a valid branch name"
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Source {
+    /// Exports of the HAW are ISO-8859-1 while local files and `CalDAV` mirrors are most likely UTF-8
+    pub fn encoding(&self) -> Encoding {
+        self.encoding.unwrap_or(match self.kind {
+            Kind::Index | Kind::MyHaw => Encoding::Latin1,
+            Kind::Local | Kind::CalDav => Encoding::Utf8OrLatin1,
+        })
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.urls.is_empty(), "needs at least one url");
+        let expected_schemes: &[&str] = match self.kind {
+            Kind::Index | Kind::MyHaw | Kind::CalDav => &["https", "http"],
+            Kind::Local => &["file"],
+        };
+        for url in &self.urls {
+            anyhow::ensure!(
+                expected_schemes.contains(&url.scheme()),
+                "{:?} url {url} should be one of {expected_schemes:?}",
+                self.kind
+            );
+        }
+        anyhow::ensure!(
+            self.kind != Kind::CalDav || self.urls.len() == 1,
+            "CalDav needs exactly one collection url"
+        );
+        anyhow::ensure!(
+            self.kind == Kind::MyHaw || self.login_url.is_none(),
+            "login-url is only supported by MyHaw"
+        );
+        anyhow::ensure!(
+            self.kind == Kind::Index || self.crawl.is_none(),
+            "crawl is only supported by Index"
+        );
+        self.sanity.validate()
+    }
+}
+
+impl LinkPattern {
+    pub fn is_match(&self, url: &Url) -> bool {
+        self.0.is_match(url.as_str())
+    }
+}
+
+impl TryFrom<String> for LinkPattern {
+    type Error = lazy_regex::regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern).map(Self)
+    }
+}
+
+impl Encoding {
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Latin1 => encoding_rs::mem::decode_latin1(bytes).into_owned(),
+            Self::Utf8OrLatin1 => str::from_utf8(bytes).map_or_else(
+                |_| encoding_rs::mem::decode_latin1(bytes).into_owned(),
+                str::to_owned,
+            ),
+            Self::Other(encoding) => encoding.decode(bytes).0.into_owned(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Encoding {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let label = String::deserialize(deserializer)?;
+        if label.eq_ignore_ascii_case("iso-8859-1") || label.eq_ignore_ascii_case("latin1") {
+            return Ok(Self::Latin1);
+        }
+        encoding_rs::Encoding::for_label(label.as_bytes())
+            .map(Self::Other)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown encoding {label:?}")))
+    }
+}
+
+#[test]
+fn shipped_config_is_valid() -> anyhow::Result<()> {
+    let config = parse(SHIPPED)?;
+    assert_eq!(config.sources.len(), 4);
+    Ok(())
+}
+
+#[test]
+fn git_defaults_to_the_eventfiles_repo() -> anyhow::Result<()> {
+    let config = parse(
+        r#"
+[git]
+branch = "staging"
+push = false
+
+[[source]]
+base = "informatik"
+urls = ["https://example.org/a"]
+"#,
+    )?;
+    assert_eq!(config.git.remote, events_git::REMOTE);
+    assert_eq!(config.git.author_name, events_git::AUTHOR_NAME);
+    assert_eq!(config.git.branch.as_deref(), Some("staging"));
+    assert!(!config.git.push);
+    assert_eq!(config.git.depth, 1);
+    Ok(())
+}
+
+#[test]
+fn duplicate_base_is_invalid() {
+    let err = parse(
+        r#"
+[[source]]
+base = "informatik"
+urls = ["https://example.org/a"]
+
+[[source]]
+base = "informatik"
+urls = ["https://example.org/b"]
+"#,
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "base informatik is configured twice");
+}
+
+#[test]
+fn encoding_defaults_by_kind() -> anyhow::Result<()> {
+    let config = parse(
+        r#"
+[[source]]
+base = "informatik"
+urls = ["https://example.org/a"]
+
+[[source]]
+base = "mailed"
+kind = "local"
+urls = ["file:///tmp/mailed"]
+
+[[source]]
+base = "other"
+kind = "caldav"
+urls = ["https://example.org/dav/"]
+encoding = "iso-8859-1"
+"#,
+    )?;
+    let encodings = config
+        .sources
+        .iter()
+        .map(Source::encoding)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        encodings,
+        [Encoding::Latin1, Encoding::Utf8OrLatin1, Encoding::Latin1]
+    );
+    assert_eq!(
+        Encoding::Utf8OrLatin1.decode("Stiftstraße".as_bytes()),
+        "Stiftstraße"
+    );
+    assert_eq!(
+        Encoding::Utf8OrLatin1.decode(b"Stiftstra\xdfe"),
+        "Stiftstraße"
+    );
+    Ok(())
+}
+
+#[test]
+fn local_source_needs_file_urls() {
+    let err = parse(
+        r#"
+[[source]]
+base = "mailed"
+kind = "local"
+urls = ["https://example.org/a.ics"]
+encoding = "utf-8"
+"#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        r#"base mailed: Local url https://example.org/a.ics should be one of ["file"]"#
+    );
+}
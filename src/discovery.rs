@@ -0,0 +1,149 @@
+//! Compare the discovered ICS urls with the ones of the last run to notice new and vanished calendars
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config::Source;
+use crate::ics_urls::Link;
+use crate::{events_git, files};
+
+/// Discovered urls by base of the last run
+pub const FILE: &str = "discovered.json";
+/// Machine readable report of the changes of the last run
+pub const REPORT_FILE: &str = "discovery-report.json";
+
+pub type Discovered = BTreeMap<String, BTreeSet<String>>;
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub new: Vec<String>,
+    pub vanished: Vec<String>,
+}
+
+/// Persist the discovered urls, report the changes to the last run and return them.
+///
+/// Untouched bases keep the urls of the last run or stay unknown when they had none.
+pub fn update(
+    previous: &Discovered,
+    discovered: &[(&Source, Vec<Link>)],
+    untouched: &[String],
+) -> BTreeMap<String, Changes> {
+    let mut current = discovered
+        .iter()
+        .map(|(source, links)| {
+            let urls = links.iter().map(|link| link.url.to_string()).collect();
+            (source.base.clone(), urls)
+        })
+        .collect::<Discovered>();
+    for base in untouched {
+        match previous.get(base) {
+            Some(urls) => current.insert(base.clone(), urls.clone()),
+            None => current.remove(base),
+        };
+    }
+
+    let report = diff(previous, &current);
+    for (base, changes) in &report {
+        println!(
+            "Discovery {base}: {} new, {} vanished",
+            changes.new.len(),
+            changes.vanished.len()
+        );
+        for url in &changes.new {
+            println!("  + {url}");
+        }
+        for url in &changes.vanished {
+            println!("  - {url}");
+        }
+    }
+
+    let folder = Path::new(events_git::FOLDER);
+    files::save_to_json(folder.join(FILE), &current);
+    files::save_to_json(folder.join(REPORT_FILE), &report);
+    report
+}
+
+/// Discovered urls of the last run
+pub fn load() -> Discovered {
+    let path = Path::new(events_git::FOLDER).join(FILE);
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Amount of urls of the base from the last run and how many of them vanished
+pub fn vanished(previous: &Discovered, base: &str, links: &[Link]) -> (usize, usize) {
+    let Some(previous) = previous.get(base) else {
+        return (0, 0);
+    };
+    let vanished = previous
+        .iter()
+        .filter(|url| !links.iter().any(|link| link.url.as_str() == url.as_str()))
+        .count();
+    (previous.len(), vanished)
+}
+
+/// Bases without changes are omitted
+fn diff(previous: &Discovered, current: &Discovered) -> BTreeMap<String, Changes> {
+    let empty = BTreeSet::new();
+    let bases = previous
+        .keys()
+        .chain(current.keys())
+        .collect::<BTreeSet<_>>();
+    let mut report = BTreeMap::new();
+    for base in bases {
+        let previous = previous.get(base).unwrap_or(&empty);
+        let current = current.get(base).unwrap_or(&empty);
+        let changes = Changes {
+            new: current.difference(previous).cloned().collect(),
+            vanished: previous.difference(current).cloned().collect(),
+        };
+        if changes != Changes::default() {
+            report.insert(base.clone(), changes);
+        }
+    }
+    report
+}
+
+#[test]
+fn diff_reports_new_and_vanished_per_base() {
+    fn discovered(entries: &[(&str, &[&str])]) -> Discovered {
+        entries
+            .iter()
+            .map(|(base, urls)| {
+                let urls = urls.iter().map(|url| (*url).to_owned()).collect();
+                ((*base).to_owned(), urls)
+            })
+            .collect()
+    }
+
+    let previous = discovered(&[
+        ("informatik", &["https://a/1.ics", "https://a/2.ics"]),
+        ("unchanged", &["https://b/1.ics"]),
+        ("removed", &["https://c/1.ics"]),
+    ]);
+    let current = discovered(&[
+        ("informatik", &["https://a/2.ics", "https://a/3.ics"]),
+        ("unchanged", &["https://b/1.ics"]),
+        ("added", &["https://d/1.ics"]),
+    ]);
+
+    let report = diff(&previous, &current);
+    assert_eq!(
+        report.keys().collect::<Vec<_>>(),
+        ["added", "informatik", "removed"]
+    );
+    assert_eq!(
+        report["informatik"],
+        Changes {
+            new: vec!["https://a/3.ics".to_owned()],
+            vanished: vec!["https://a/1.ics".to_owned()],
+        }
+    );
+    assert_eq!(report["removed"].vanished, ["https://c/1.ics"]);
+    assert_eq!(report["added"].new, ["https://d/1.ics"]);
+}
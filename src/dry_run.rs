@@ -0,0 +1,69 @@
+//! Run the whole pipeline on a copy of the local folders to see what would change without publishing anything.
+//!
+//! The local events checkout is compared as it is, it is not pulled before.
+
+use std::path::Path;
+use std::{env, fs};
+
+use chrono::{SubsecRound as _, Utc};
+
+use crate::config::Config;
+use crate::{caldav, events_git, files, schema, v4, v5};
+
+/// Returns the bases which failed their sanity checks
+pub fn run(config: &Config) -> anyhow::Result<Vec<String>> {
+    let real = env::current_dir()?;
+    // the history of the real checkout is read for the metadata as the copy has none
+    let repo = events_git::open(&config.git, real.join(events_git::FOLDER));
+    let copy = tempfile::tempdir()?;
+    for folder in [events_git::FOLDER, v4::FOLDER, caldav::FOLDER] {
+        let path = real.join(folder);
+        if path.exists() {
+            files::copy_dir(&path, &copy.path().join(folder))?;
+        }
+    }
+    if !real.join(events_git::FOLDER).exists() {
+        println!("WARNING: there is no events checkout yet, every file is reported as added");
+        fs::create_dir(copy.path().join(events_git::FOLDER))?;
+    }
+
+    // all folders are relative so the pipeline works on the copy
+    env::set_current_dir(copy.path())?;
+    let (summary, _) = crate::download_ics(&*repo, &config.sources);
+    println!("\n\n## Generate merged eventfiles compatible with downloader v4");
+    v4::update();
+    println!("\n\n## Generate v5 eventfiles");
+    v5::update(Utc::now().trunc_subsecs(0));
+    schema::save_eventfiles();
+    env::set_current_dir(&real)?;
+
+    println!(
+        "\n\n## Dry run commit message\n{}",
+        summary.commit_message()
+    );
+    for folder in [events_git::FOLDER, v4::FOLDER] {
+        print_diff(folder, &real.join(folder), &copy.path().join(folder))?;
+    }
+    Ok(summary.failed.into_keys().collect())
+}
+
+fn print_diff(folder: &str, real: &Path, copy: &Path) -> anyhow::Result<()> {
+    let changes = files::diff_dirs(real, copy)?;
+    println!("\n## Dry run changes in {folder}");
+    for (status, path) in &changes {
+        println!("{status} {}", path.display());
+    }
+    let amount = |expected| {
+        changes
+            .iter()
+            .filter(|(status, _)| *status == expected)
+            .count()
+    };
+    println!(
+        "{folder}: {} added, {} changed, {} removed files",
+        amount('A'),
+        amount('M'),
+        amount('D')
+    );
+    Ok(())
+}
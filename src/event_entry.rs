@@ -1,8 +1,17 @@
-use chrono::{NaiveDateTime, TimeZone as _};
+use anyhow::Context as _;
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeZone as _};
 use chrono_tz::Europe::Berlin;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+use crate::event_files::SourcedEvent;
+use crate::manifest;
+
+/// Prefix of the description generated from the lecturers of an ICS event
+const LECTURER_PREFIX: &str = "Dozent:";
+
+/// Event of an eventfile in the events repo
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct EventEntry {
     pub name: String,
@@ -10,17 +19,59 @@ pub struct EventEntry {
     pub description: String,
     pub start: NaiveDateTime,
     pub end: NaiveDateTime,
+    /// UID of the ICS event. Files written before it was recorded do not have it.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uid: String,
+}
+
+impl EventEntry {
+    /// Identifies the event across runs. Without an UID only unchanged events are recognized.
+    pub fn key(&self) -> String {
+        if self.uid.is_empty() {
+            self.fields_key()
+        } else {
+            self.uid.clone()
+        }
+    }
+
+    /// Key of the event when it has no UID
+    pub fn fields_key(&self) -> String {
+        manifest::hash(&format!(
+            "{}\n{}\n{}\n{}",
+            self.name, self.location, self.start, self.end
+        ))
+    }
 }
 
-#[derive(Serialize, Debug, PartialEq, Eq)]
+#[cfg(test)]
+impl EventEntry {
+    /// Event of BAI1-SE1 on 2026-10-20 08:00 for tests
+    pub fn example(uid: &str, location: &str) -> Self {
+        let start = NaiveDateTime::parse_from_str("2026-10-20 08:00", "%Y-%m-%d %H:%M")
+            .expect("example date should be valid");
+        Self {
+            name: "BAI1-SE1".to_owned(),
+            location: location.to_owned(),
+            description: String::new(),
+            start,
+            end: start,
+            uid: uid.to_owned(),
+        }
+    }
+}
+
+/// Event of a v4 eventfile
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
 pub struct EventEntryV4 {
     pub name: String,
     pub location: String,
     pub description: String,
     #[serde(serialize_with = "serialize_date_time")]
+    #[schemars(with = "DateTime<FixedOffset>")]
     pub start_time: NaiveDateTime,
     #[serde(serialize_with = "serialize_date_time")]
+    #[schemars(with = "DateTime<FixedOffset>")]
     pub end_time: NaiveDateTime,
 }
 
@@ -42,3 +93,82 @@ impl From<EventEntry> for EventEntryV4 {
         }
     }
 }
+
+/// Event of a v5 eventfile
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EventEntryV5 {
+    /// UID of the ICS event. Missing for events of files written before it was recorded.
+    pub uid: Option<String>,
+    pub name: String,
+    pub base: String,
+    /// Eventfile of the base the event was read from
+    pub source_file: String,
+    pub lecturers: Vec<String>,
+    pub rooms: Vec<String>,
+    pub description: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    /// IANA timezone the times are local to
+    pub timezone: String,
+}
+
+fn split_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn in_berlin(dt: &NaiveDateTime) -> anyhow::Result<DateTime<FixedOffset>> {
+    Ok(Berlin
+        .from_local_datetime(dt)
+        .earliest()
+        .with_context(|| format!("{dt} does not exist in Europe/Berlin"))?
+        .fixed_offset())
+}
+
+impl TryFrom<SourcedEvent> for EventEntryV5 {
+    type Error = anyhow::Error;
+
+    fn try_from(value: SourcedEvent) -> Result<Self, Self::Error> {
+        let event = value.event;
+        let lecturers = event
+            .description
+            .strip_prefix(LECTURER_PREFIX)
+            .map(split_list)
+            .unwrap_or_default();
+        Ok(Self {
+            uid: (!event.uid.is_empty()).then_some(event.uid),
+            rooms: split_list(&event.location),
+            lecturers,
+            start: in_berlin(&event.start)?,
+            end: in_berlin(&event.end)?,
+            timezone: Berlin.name().to_owned(),
+            name: event.name,
+            base: value.base,
+            source_file: value.file,
+            description: event.description,
+        })
+    }
+}
+
+#[test]
+fn v5_has_structured_fields() -> anyhow::Result<()> {
+    let sourced = SourcedEvent {
+        base: "informatik".to_owned(),
+        file: "BAI1-SE1.json".to_owned(),
+        event: EventEntry {
+            description: "Dozent: HTM, ABC".to_owned(),
+            ..EventEntry::example("", "1.01, 1.02")
+        },
+    };
+    let v5 = EventEntryV5::try_from(sourced)?;
+    assert_eq!(v5.uid, None);
+    assert_eq!(v5.lecturers, ["HTM", "ABC"]);
+    assert_eq!(v5.rooms, ["1.01", "1.02"]);
+    assert_eq!(v5.start.to_rfc3339(), "2026-10-20T08:00:00+02:00");
+    assert_eq!(v5.timezone, "Europe/Berlin");
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Read the eventfiles of all bases from the events folder
+
+use std::fs;
+use std::path::Path;
+
+use crate::{EventEntry, events_git, files, metadata};
+
+/// Event together with the eventfile it was read from
+#[derive(Debug)]
+pub struct SourcedEvent {
+    pub base: String,
+    /// File name within the base including the extension
+    pub file: String,
+    pub event: EventEntry,
+}
+
+pub fn read_all() -> anyhow::Result<Vec<SourcedEvent>> {
+    let mut result = Vec::new();
+    for entry in fs::read_dir(events_git::FOLDER)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let file_name = entry.file_name();
+        let file_name = file_name
+            .to_str()
+            .expect("events should only contain unicode filenames");
+        if file_name.starts_with('.') || file_name == metadata::FOLDER {
+            continue;
+        }
+        result.append(&mut read_base(file_name, &entry.path())?);
+    }
+    Ok(result)
+}
+
+fn read_base(base: &str, base_path: &Path) -> anyhow::Result<Vec<SourcedEvent>> {
+    let mut result = Vec::new();
+    for file in files::json_files(base_path)? {
+        let content = fs::read_to_string(base_path.join(&file))?;
+        let events: Vec<EventEntry> = serde_json::from_str(&content)?;
+        result.extend(events.into_iter().map(|event| SourcedEvent {
+            base: base.to_owned(),
+            file: file.clone(),
+            event,
+        }));
+    }
+    Ok(result)
+}
@@ -1,69 +1,552 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
+use std::{fmt, fs};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{Git, GitBackend};
+use crate::events_gix::Gix;
 
 pub const FOLDER: &str = "events";
+/// Defaults of the `[git]` config
+pub const REMOTE: &str = "git@github.com:HAWHHCalendarBot/eventfiles.git";
+pub const AUTHOR_NAME: &str = "downloader";
+pub const AUTHOR_EMAIL: &str = "calendarbot-downloader@hawhh.de";
 
-fn raw(args: &[&str]) -> ExitStatus {
-    Command::new("git")
-        .args(args)
-        .current_dir(FOLDER)
-        .status()
-        .expect("git process should execute")
+#[derive(Debug)]
+pub enum GitError {
+    /// The git process could not be started
+    Spawn(std::io::Error),
+    /// git ran but exited with a failure status
+    Failed {
+        args: Vec<String>,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// The remote has commits which were not pulled before the push
+    Rejected(String),
+    /// The existing checkout follows another remote or branch than configured
+    Mismatch(String),
+    /// The pure-Rust implementation failed
+    Gix {
+        operation: &'static str,
+        source: anyhow::Error,
+    },
 }
 
-fn successful(args: &[&str]) {
-    let status = raw(args);
-    assert!(
-        status.success(),
-        "failed git command. Status code {status}. git {args:?}"
-    );
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Spawn(err) => write!(f, "failed to execute git: {err}"),
+            Self::Failed {
+                args,
+                status,
+                stderr,
+            } => write!(f, "git {args:?} failed with {status}: {}", stderr.trim()),
+            Self::Rejected(reason) => write!(f, "push rejected by the remote: {reason}"),
+            Self::Mismatch(reason) => write!(
+                f,
+                "checkout does not match the config: {reason}. Remove the checkout to clone it again."
+            ),
+            Self::Gix { operation, source } => write!(f, "gix {operation} failed: {source:#}"),
+        }
+    }
 }
 
-pub fn pull() {
-    if Path::new(FOLDER).join(".git").exists() {
-        successful(&["pull", "--ff-only"]);
-    } else {
-        let status = Command::new("git")
-            .args([
-                "clone",
-                "-q",
-                "--depth",
-                "1",
-                "git@github.com:HAWHHCalendarBot/eventfiles.git",
-                FOLDER,
-            ])
-            .status()
-            .expect("git process should execute");
-        assert!(status.success(), "git clone status code {status}");
+impl std::error::Error for GitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Spawn(err) => Some(err),
+            Self::Failed { .. } | Self::Rejected(_) | Self::Mismatch(_) => None,
+            Self::Gix { source, .. } => Some(source.as_ref()),
+        }
     }
 }
 
-pub fn add(arg: &str) {
-    successful(&["add", arg]);
+/// Entry of a directory within a commit
+#[derive(Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
 }
 
-fn commit() {
-    let _status = raw(&[
-        "commit",
-        "--no-gpg-sign",
-        "--author",
-        "downloader <calendarbot-downloader@hawhh.de>",
-        "--message",
-        "update",
-    ]);
-    // ignore status as "nothing to commit" is also a non success. Maybe handle in a better way my checking stdout.
+/// Operations needed on the working copy of the events repo
+pub trait GitRepo: fmt::Debug {
+    fn exists(&self) -> bool;
+
+    /// Clone the configured remote into the path of this repo
+    fn clone_remote(&self) -> Result<(), GitError>;
+
+    /// Fast-forward to the upstream branch
+    fn pull(&self) -> Result<(), GitError>;
+
+    /// Stage the file or directory including deletions within it
+    fn add(&self, pathspec: &str) -> Result<(), GitError>;
+
+    /// Whether the index differs from HEAD
+    fn has_staged_changes(&self) -> Result<bool, GitError>;
+
+    /// Commit the staged changes. Having nothing to commit is an error.
+    fn commit(&self, message: &str) -> Result<(), GitError>;
+
+    /// Push the branch. A remote with newer commits results in [`GitError::Rejected`].
+    fn push(&self) -> Result<(), GitError>;
+
+    /// Fetch and move HEAD and the index to the upstream branch while keeping the working tree
+    fn reset_to_upstream(&self) -> Result<(), GitError>;
+
+    /// Overwrite working tree files which differ from the index
+    fn discard_unstaged(&self) -> Result<(), GitError>;
+
+    /// Id of the newest commit of HEAD which was committed at or before the time.
+    /// None when the history does not reach back that far.
+    fn commit_before(&self, time: DateTime<Utc>) -> Result<Option<String>, GitError>;
+
+    /// Entries of the directory within the commit. An empty dir is the root of the repo.
+    fn list_dir(&self, commit: &str, dir: &str) -> Result<Vec<DirEntry>, GitError>;
+
+    fn read_file(&self, commit: &str, path: &str) -> Result<String, GitError>;
+}
+
+/// Ensure the remote url and branch of an existing checkout are the configured ones
+pub fn check_checkout(config: &Git, remote: &str, branch: &str) -> Result<(), GitError> {
+    if remote != config.remote {
+        return Err(GitError::Mismatch(format!(
+            "remote is {remote} instead of {}",
+            config.remote
+        )));
+    }
+    if let Some(configured) = &config.branch
+        && branch != configured
+    {
+        return Err(GitError::Mismatch(format!(
+            "branch {branch} is checked out instead of {configured}"
+        )));
+    }
+    Ok(())
 }
 
-pub fn commit_and_push() {
-    commit();
+/// Let git ignore the untracked path within the checkout by listing it in `.git/info/exclude`
+pub fn exclude(checkout: &Path, path: &str) -> std::io::Result<()> {
+    let info = checkout.join(".git/info");
+    let file = info.join("exclude");
+    let pattern = format!("/{path}/");
+    let mut content = fs::read_to_string(&file).unwrap_or_default();
+    if content.lines().any(|line| line == pattern) {
+        return Ok(());
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&pattern);
+    content.push('\n');
+    fs::create_dir_all(info)?;
+    fs::write(file, content)
+}
+
+pub fn open<P: Into<PathBuf>>(config: &Git, path: P) -> Box<dyn GitRepo> {
+    match config.backend {
+        GitBackend::Cli => Box::new(GitCli::new(config.clone(), path)),
+        GitBackend::Gix => Box::new(Gix::new(config.clone(), path)),
+    }
+}
 
-    #[cfg(not(debug_assertions))]
-    successful(&["push"]);
+/// Git working copy at the path accessed with the `git` binary
+#[derive(Debug)]
+pub struct GitCli {
+    config: Git,
+    path: PathBuf,
 }
 
-/// Roll back to the versions from the repo.
-///
-/// This is needed when some base url failed during the download but others went fine.
-pub fn checkout() {
-    successful(&["checkout", "."]);
+impl GitCli {
+    pub fn new<P: Into<PathBuf>>(config: Git, path: P) -> Self {
+        Self {
+            config,
+            path: path.into(),
+        }
+    }
+
+    /// Run git with the args and return its stdout
+    fn git(current_dir: &Path, args: &[&str]) -> Result<String, GitError> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(current_dir)
+            .output()
+            .map_err(GitError::Spawn)?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(GitError::Failed {
+                args: args.iter().map(|&arg| arg.to_owned()).collect(),
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            })
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String, GitError> {
+        Self::git(&self.path, args)
+    }
+}
+
+impl GitRepo for GitCli {
+    fn exists(&self) -> bool {
+        self.path.join(".git").exists()
+    }
+
+    fn clone_remote(&self) -> Result<(), GitError> {
+        let path = self.path.to_string_lossy();
+        let depth = self.config.depth.to_string();
+        let mut args = vec!["clone", "-q"];
+        if self.config.depth > 0 {
+            args.extend(["--depth", &depth]);
+        }
+        if let Some(branch) = &self.config.branch {
+            args.extend(["--branch", branch]);
+        }
+        args.extend([self.config.remote.as_str(), &path]);
+        Self::git(Path::new("."), &args)?;
+        Ok(())
+    }
+
+    fn pull(&self) -> Result<(), GitError> {
+        let branch = self.run(&["symbolic-ref", "--short", "HEAD"])?;
+        let remote = self.run(&["ls-remote", "--get-url"])?;
+        check_checkout(&self.config, remote.trim(), branch.trim())?;
+        self.run(&["pull", "-q", "--ff-only"])?;
+        Ok(())
+    }
+
+    fn add(&self, pathspec: &str) -> Result<(), GitError> {
+        self.run(&["add", pathspec])?;
+        Ok(())
+    }
+
+    fn has_staged_changes(&self) -> Result<bool, GitError> {
+        // --quiet exits with 1 when there are differences
+        match self.run(&["diff", "--cached", "--quiet"]) {
+            Ok(_) => Ok(false),
+            Err(GitError::Failed { status, .. }) if status.code() == Some(1) => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn commit(&self, message: &str) -> Result<(), GitError> {
+        let author = format!("{} <{}>", self.config.author_name, self.config.author_email);
+        self.run(&[
+            "commit",
+            "-q",
+            "--no-gpg-sign",
+            "--author",
+            &author,
+            "--message",
+            message,
+        ])?;
+        Ok(())
+    }
+
+    fn push(&self) -> Result<(), GitError> {
+        match self.run(&["push", "-q"]) {
+            Ok(_) => Ok(()),
+            Err(GitError::Failed { stderr, .. }) if stderr.contains("[rejected]") => {
+                Err(GitError::Rejected(stderr.trim().to_owned()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn reset_to_upstream(&self) -> Result<(), GitError> {
+        self.run(&["fetch", "-q"])?;
+        self.run(&["reset", "-q", "@{upstream}"])?;
+        Ok(())
+    }
+
+    fn discard_unstaged(&self) -> Result<(), GitError> {
+        self.run(&["checkout", "-q", "--", "."])?;
+        Ok(())
+    }
+
+    fn commit_before(&self, time: DateTime<Utc>) -> Result<Option<String>, GitError> {
+        let before = format!("--before={}", time.to_rfc3339());
+        let id = self.run(&["rev-list", "-1", &before, "HEAD"])?;
+        let id = id.trim();
+        Ok((!id.is_empty()).then(|| id.to_owned()))
+    }
+
+    fn list_dir(&self, commit: &str, dir: &str) -> Result<Vec<DirEntry>, GitError> {
+        let tree = format!("{commit}:{dir}");
+        let output = self.run(&["ls-tree", "-z", &tree])?;
+        Ok(output
+            .split_terminator('\0')
+            .filter_map(|line| {
+                // <mode> SP <type> SP <object> TAB <file>
+                let (info, name) = line.split_once('\t')?;
+                Some(DirEntry {
+                    name: name.to_owned(),
+                    is_dir: info.split(' ').nth(1) == Some("tree"),
+                })
+            })
+            .collect())
+    }
+
+    fn read_file(&self, commit: &str, path: &str) -> Result<String, GitError> {
+        self.run(&["show", &format!("{commit}:{path}")])
+    }
+}
+
+#[cfg(test)]
+fn git_in(dir: &Path, args: &[&str]) -> String {
+    GitCli::git(dir, args).unwrap()
+}
+
+/// Clone, commit, push and pull against a local bare repo
+#[cfg(test)]
+fn exercise_backend(backend: GitBackend) -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let remote = dir.path().join("remote.git");
+    git_in(dir.path(), &["init", "-q", "--bare", "remote.git"]);
+    git_in(dir.path(), &["clone", "-q", "remote.git", "other"]);
+    let other = dir.path().join("other");
+    git_in(&other, &["config", "user.name", "test"]);
+    git_in(&other, &["config", "user.email", "test@example.org"]);
+    fs::write(other.join("README.md"), "events")?;
+    git_in(&other, &["add", "README.md"]);
+    git_in(&other, &["commit", "-q", "--message", "init"]);
+    git_in(&other, &["push", "-q", "origin", "HEAD"]);
+
+    let path = dir.path().join("work");
+    let config = Git {
+        backend,
+        remote: remote.to_string_lossy().into_owned(),
+        ..Git::default()
+    };
+    let repo = open(&config, &path);
+    assert!(!repo.exists());
+    repo.clone_remote()?;
+    assert!(repo.exists());
+    git_in(&path, &["config", "user.name", "test"]);
+    git_in(&path, &["config", "user.email", "test@example.org"]);
+
+    fs::create_dir(path.join("informatik"))?;
+    fs::write(path.join("informatik/a.json"), "[]")?;
+    assert!(!repo.has_staged_changes()?);
+    repo.add("informatik")?;
+    assert!(repo.has_staged_changes()?);
+    repo.commit("add a")?;
+    assert!(!repo.has_staged_changes()?);
+    assert!(repo.commit("nothing to commit").is_err());
+    repo.push()?;
+    assert_eq!(
+        git_in(&remote, &["log", "--format=%s %an"]),
+        "add a downloader\ninit test\n"
+    );
+    assert_eq!(git_in(&path, &["status", "--porcelain"]), "");
+
+    git_in(&other, &["pull", "-q"]);
+    git_in(&other, &["rm", "-q", "informatik/a.json"]);
+    fs::write(other.join("informatik.json"), "{}")?;
+    git_in(&other, &["add", "informatik.json"]);
+    git_in(&other, &["commit", "-q", "--message", "replace a"]);
+    git_in(&other, &["push", "-q"]);
+
+    repo.pull()?;
+    assert!(!path.join("informatik").exists());
+    assert_eq!(fs::read_to_string(path.join("informatik.json"))?, "{}");
+    assert_eq!(git_in(&path, &["status", "--porcelain"]), "");
+
+    // another process pushes between our pull and push
+    fs::write(other.join("README.md"), "changed elsewhere")?;
+    git_in(&other, &["commit", "-q", "--all", "--message", "readme"]);
+    git_in(&other, &["push", "-q"]);
+    fs::create_dir_all(path.join("informatik"))?;
+    fs::write(path.join("informatik/b.json"), "[]")?;
+    repo.add("informatik")?;
+    repo.commit("add b")?;
+    let err = repo.push().unwrap_err();
+    assert!(matches!(err, GitError::Rejected(_)), "{err}");
+
+    repo.reset_to_upstream()?;
+    repo.add("informatik")?;
+    repo.commit("add b")?;
+    repo.discard_unstaged()?;
+    repo.push()?;
+    assert_eq!(
+        git_in(&remote, &["log", "--format=%s", "-2"]),
+        "add b\nreadme\n"
+    );
+    assert_eq!(
+        fs::read_to_string(path.join("README.md"))?,
+        "changed elsewhere"
+    );
+    assert_eq!(git_in(&path, &["status", "--porcelain"]), "");
+
+    let commit = repo
+        .commit_before(Utc::now())?
+        .expect("should have commits");
+    assert_eq!(commit, git_in(&path, &["rev-parse", "HEAD"]).trim());
+    let past = DateTime::parse_from_rfc3339("2000-01-01T00:00:00Z")?.to_utc();
+    assert_eq!(repo.commit_before(past)?, None);
+    let root = repo.list_dir(&commit, "")?;
+    assert!(root.contains(&DirEntry {
+        name: "informatik".to_owned(),
+        is_dir: true
+    }));
+    assert!(root.contains(&DirEntry {
+        name: "README.md".to_owned(),
+        is_dir: false
+    }));
+    let names = repo
+        .list_dir(&commit, "informatik")?
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["b.json"]);
+    assert_eq!(repo.read_file(&commit, "informatik/b.json")?, "[]");
+
+    exercise_dirty_pull(&*repo, &path, &other)?;
+    exercise_mismatch(&config, &path)?;
+    #[cfg(unix)]
+    exercise_modes(&*repo, &path, &other)?;
+    Ok(())
+}
+
+/// Pulling refuses to overwrite local changes and untracked files
+#[cfg(test)]
+fn exercise_dirty_pull(repo: &dyn GitRepo, path: &Path, other: &Path) -> anyhow::Result<()> {
+    git_in(other, &["pull", "-q"]);
+    fs::write(other.join("README.md"), "changed again")?;
+    fs::write(other.join("notes.txt"), "from elsewhere")?;
+    git_in(other, &["add", "README.md", "notes.txt"]);
+    git_in(other, &["commit", "-q", "--message", "notes"]);
+    git_in(other, &["push", "-q"]);
+
+    fs::write(path.join("README.md"), "local edit")?;
+    assert!(repo.pull().is_err());
+    assert_eq!(fs::read_to_string(path.join("README.md"))?, "local edit");
+
+    git_in(path, &["checkout", "README.md"]);
+    fs::write(path.join("notes.txt"), "untracked")?;
+    assert!(repo.pull().is_err());
+    assert_eq!(fs::read_to_string(path.join("notes.txt"))?, "untracked");
+
+    fs::remove_file(path.join("notes.txt"))?;
+    repo.pull()?;
+    assert_eq!(fs::read_to_string(path.join("README.md"))?, "changed again");
+    assert_eq!(git_in(path, &["status", "--porcelain"]), "");
+    Ok(())
+}
+
+/// Pulling refuses a checkout of another remote or branch
+#[cfg(test)]
+fn exercise_mismatch(config: &Git, path: &Path) -> anyhow::Result<()> {
+    let elsewhere = Git {
+        remote: "https://example.org/elsewhere.git".to_owned(),
+        ..config.clone()
+    };
+    let err = open(&elsewhere, path).pull().unwrap_err();
+    assert!(matches!(err, GitError::Mismatch(_)), "{err}");
+
+    let other_branch = Git {
+        branch: Some("other".to_owned()),
+        ..config.clone()
+    };
+    let err = open(&other_branch, path).pull().unwrap_err();
+    assert!(matches!(err, GitError::Mismatch(_)), "{err}");
+
+    open(config, path).pull()?;
+    Ok(())
+}
+
+/// Executables and symlinks keep their mode
+#[cfg(all(test, unix))]
+fn exercise_modes(repo: &dyn GitRepo, path: &Path, other: &Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::{PermissionsExt as _, symlink};
+
+    fs::create_dir(other.join("tools"))?;
+    fs::write(other.join("tools/run.sh"), "#!/bin/sh")?;
+    fs::set_permissions(
+        other.join("tools/run.sh"),
+        fs::Permissions::from_mode(0o755),
+    )?;
+    symlink("run.sh", other.join("tools/link"))?;
+    git_in(other, &["pull", "-q"]);
+    git_in(other, &["add", "tools"]);
+    git_in(other, &["commit", "-q", "--message", "tools"]);
+    git_in(other, &["push", "-q"]);
+
+    repo.pull()?;
+    assert!(!repo.has_staged_changes()?);
+    repo.add("tools")?;
+    assert!(!repo.has_staged_changes()?);
+    fs::write(path.join("tools/run.sh"), "#!/bin/sh\nexit 0")?;
+    repo.add("tools")?;
+    repo.commit("change run")?;
+    assert_eq!(
+        git_in(
+            path,
+            &[
+                "ls-tree",
+                "--format=%(objectmode) %(path)",
+                "HEAD",
+                "tools/"
+            ]
+        ),
+        "120000 tools/link\n100755 tools/run.sh\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn cli_backend_against_bare_repo() -> anyhow::Result<()> {
+    exercise_backend(GitBackend::Cli)
+}
+
+#[test]
+fn gix_backend_against_bare_repo() -> anyhow::Result<()> {
+    exercise_backend(GitBackend::Gix)
+}
+
+#[test]
+fn failure_contains_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = GitCli::new(Git::default(), dir.path()).pull().unwrap_err();
+    let GitError::Failed { stderr, .. } = &err else {
+        panic!("should have run git: {err}");
+    };
+    assert!(stderr.contains("not a git repository"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn commit_hook_failure_is_reported() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let dir = tempfile::tempdir()?;
+    git_in(dir.path(), &["init", "-q"]);
+    git_in(dir.path(), &["config", "user.name", "test"]);
+    git_in(dir.path(), &["config", "user.email", "test@example.org"]);
+    let hook = dir.path().join(".git/hooks/pre-commit");
+    fs::write(&hook, "#!/bin/sh\necho rejected by hook >&2\nexit 1\n")?;
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+    fs::write(dir.path().join("a.json"), "[]")?;
+
+    let repo = GitCli::new(Git::default(), dir.path());
+    repo.add("a.json")?;
+    let err = repo.commit("add a").unwrap_err();
+    assert!(err.to_string().contains("rejected by hook"), "{err}");
+    Ok(())
+}
+
+#[test]
+fn exclude_is_listed_once() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir_all(dir.path().join(".git/info"))?;
+    fs::write(dir.path().join(".git/info/exclude"), "# git ls-files")?;
+    exclude(dir.path(), ".staging")?;
+    exclude(dir.path(), ".staging")?;
+    assert_eq!(
+        fs::read_to_string(dir.path().join(".git/info/exclude"))?,
+        "# git ls-files\n/.staging/\n"
+    );
+    Ok(())
 }
@@ -0,0 +1,576 @@
+//! Pure-Rust access to the events repo based on gitoxide.
+//!
+//! gitoxide does not support pushing yet so the pack is generated and sent to `git-receive-pack` directly.
+
+use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use gix::progress::Discard;
+use gix::protocol::transport::client::blocking_io::{Transport as _, connect, ssh};
+use gix::protocol::transport::client::{MessageKind, WriteMode};
+use gix::remote::Direction;
+use gix_pack::data::output;
+
+use crate::config::Git;
+use crate::events_git::{DirEntry, GitError, GitRepo, check_checkout};
+
+/// The remote has commits which are not part of the pushed history
+#[derive(Debug)]
+struct Rejected(String);
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+#[derive(Debug)]
+pub struct Gix {
+    config: Git,
+    path: PathBuf,
+}
+
+fn wrap<T>(operation: &'static str, result: anyhow::Result<T>) -> Result<T, GitError> {
+    result.map_err(|source| GitError::Gix { operation, source })
+}
+
+impl Gix {
+    pub fn new<P: Into<PathBuf>>(config: Git, path: P) -> Self {
+        Self {
+            config,
+            path: path.into(),
+        }
+    }
+
+    fn open(&self) -> anyhow::Result<gix::Repository> {
+        // reflog entries need a committer even when none is configured
+        let options = gix::open::Options::default().config_overrides([
+            format!(
+                "gitoxide.committer.nameFallback={}",
+                self.config.author_name
+            ),
+            format!(
+                "gitoxide.committer.emailFallback={}",
+                self.config.author_email
+            ),
+        ]);
+        Ok(gix::open_opts(&self.path, options)?)
+    }
+
+    fn clone_inner(&self) -> anyhow::Result<()> {
+        let interrupt = AtomicBool::new(false);
+        let mut prepare = gix::prepare_clone(self.config.remote.as_str(), &self.path)?
+            .with_ref_name(self.config.branch.as_deref())?;
+        if let Ok(depth) = self.config.depth.try_into() {
+            prepare = prepare.with_shallow(gix::remote::fetch::Shallow::DepthAtRemote(depth));
+        }
+        let (mut checkout, _) = prepare.fetch_then_checkout(Discard, &interrupt)?;
+        checkout.main_worktree(Discard, &interrupt)?;
+        Ok(())
+    }
+
+    /// Fetch the remote and return the id of the upstream of the checked out branch
+    fn fetch(repo: &gix::Repository) -> anyhow::Result<gix::ObjectId> {
+        let interrupt = AtomicBool::new(false);
+        let remote = repo
+            .find_default_remote(Direction::Fetch)
+            .context("should have a remote")??;
+        remote
+            .connect(Direction::Fetch)?
+            .prepare_fetch(Discard, gix::remote::ref_map::Options::default())?
+            .receive(Discard, &interrupt)?;
+
+        let branch = repo.head_name()?.context("HEAD should be a branch")?;
+        let tracking = repo
+            .branch_remote_tracking_ref_name(branch.as_ref(), Direction::Fetch)
+            .context("branch should have an upstream")??;
+        Ok(repo
+            .find_reference(tracking.as_ref())?
+            .peel_to_id()?
+            .detach())
+    }
+
+    /// Write the files of the index into the working tree and the index to disk
+    fn checkout(repo: &gix::Repository, mut index: gix::index::File) -> anyhow::Result<()> {
+        let interrupt = AtomicBool::new(false);
+        let workdir = repo.workdir().context("should not be bare")?;
+        let mut options =
+            repo.checkout_options(gix::worktree::stack::state::attributes::Source::IdMapping)?;
+        options.overwrite_existing = true;
+        gix::worktree::state::checkout(
+            &mut index,
+            workdir,
+            repo.objects.clone().into_arc()?,
+            &Discard,
+            &Discard,
+            &interrupt,
+            options,
+        )?;
+        index.write(gix::index::write::Options::default())?;
+        Ok(())
+    }
+
+    /// Fetch url of the default remote and the checked out branch
+    fn checked_out(&self) -> anyhow::Result<(String, String)> {
+        let repo = self.open()?;
+        let remote = repo
+            .find_default_remote(Direction::Fetch)
+            .context("should have a remote")??;
+        let url = remote
+            .url(Direction::Fetch)
+            .context("remote should have a url")?
+            .to_bstring()
+            .to_string();
+        let branch = repo.head_name()?.context("HEAD should be a branch")?;
+        Ok((url, branch.shorten().to_string()))
+    }
+
+    fn pull_inner(&self) -> anyhow::Result<()> {
+        let repo = self.open()?;
+        let upstream = Self::fetch(&repo)?;
+        let head = repo.head_id()?.detach();
+        let branch = repo.head_name()?.context("HEAD should be a branch")?;
+        if upstream == head {
+            return Ok(());
+        }
+        let is_fast_forward = repo
+            .rev_walk([upstream])
+            .all()?
+            .filter_map(Result::ok)
+            .any(|info| info.id == head);
+        anyhow::ensure!(is_fast_forward, "upstream {upstream} is not a fast-forward");
+
+        let workdir = repo.workdir().context("should not be bare")?;
+        let previous_index = repo.open_index()?;
+        let tree = repo.find_commit(upstream)?.tree_id()?.detach();
+        let index = repo.index_from_tree(&tree)?;
+        // refuse like git instead of overwriting local changes
+        let changed = Self::unstaged_changes(&repo, &previous_index)?;
+        anyhow::ensure!(
+            changed.is_empty(),
+            "local changes would be overwritten by the pull: {}",
+            changed.join(", ")
+        );
+        for entry in index.entries() {
+            let path = entry.path(&index);
+            let in_the_way = previous_index.entry_by_path(path).is_none()
+                && fs::symlink_metadata(workdir.join(gix::path::from_bstr(path)?)).is_ok();
+            anyhow::ensure!(
+                !in_the_way,
+                "untracked {path} would be overwritten by the pull"
+            );
+        }
+
+        for entry in previous_index.entries() {
+            let path = entry.path(&previous_index);
+            if index.entry_by_path(path).is_none() {
+                let file = workdir.join(gix::path::from_bstr(path)?);
+                if fs::symlink_metadata(&file).is_ok() {
+                    fs::remove_file(&file)?;
+                }
+                // like git remove the directories which became empty
+                let mut dir = file.parent();
+                while let Some(current) = dir
+                    && current != workdir
+                    && fs::remove_dir(current).is_ok()
+                {
+                    dir = current.parent();
+                }
+            }
+        }
+        Self::checkout(&repo, index)?;
+        repo.reference(
+            branch,
+            upstream,
+            gix::refs::transaction::PreviousValue::MustExistAndMatch(head.into()),
+            "pull: fast-forward",
+        )?;
+        Ok(())
+    }
+
+    /// Paths of tracked files whose working tree version differs from the index
+    fn unstaged_changes(
+        repo: &gix::Repository,
+        index: &gix::index::File,
+    ) -> anyhow::Result<Vec<String>> {
+        let workdir = repo.workdir().context("should not be bare")?;
+        let mut changed = Vec::new();
+        for entry in index.entries() {
+            let path = entry.path(index);
+            let file = workdir.join(gix::path::from_bstr(path)?);
+            let content = match fs::symlink_metadata(&file) {
+                Ok(metadata) if metadata.is_symlink() => {
+                    gix::path::into_bstr(fs::read_link(&file)?)?
+                        .into_owned()
+                        .into()
+                }
+                Ok(metadata) if metadata.is_file() => fs::read(&file)?,
+                _ => {
+                    changed.push(path.to_string());
+                    continue;
+                }
+            };
+            let id =
+                gix::objs::compute_hash(repo.object_hash(), gix::object::Kind::Blob, &content)?;
+            if id != entry.id {
+                changed.push(path.to_string());
+            }
+        }
+        Ok(changed)
+    }
+
+    fn reset_to_upstream_inner(&self) -> anyhow::Result<()> {
+        let repo = self.open()?;
+        let upstream = Self::fetch(&repo)?;
+        let branch = repo.head_name()?.context("HEAD should be a branch")?;
+        let tree = repo.find_commit(upstream)?.tree_id()?.detach();
+        let mut index = repo.index_from_tree(&tree)?;
+        index.write(gix::index::write::Options::default())?;
+        repo.reference(
+            branch,
+            upstream,
+            gix::refs::transaction::PreviousValue::MustExist,
+            "reset: moving to upstream",
+        )?;
+        Ok(())
+    }
+
+    fn commit_before_inner(&self, time: DateTime<Utc>) -> anyhow::Result<Option<String>> {
+        let repo = self.open()?;
+        let head = repo.head_id()?.detach();
+        for info in repo.rev_walk([head]).all()? {
+            let commit = repo.find_commit(info?.id)?;
+            if commit.time()?.seconds <= time.timestamp() {
+                return Ok(Some(commit.id.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Tree or blob at the path within the commit
+    fn find_in_commit<'repo>(
+        repo: &'repo gix::Repository,
+        commit: &str,
+        path: &str,
+    ) -> anyhow::Result<gix::Object<'repo>> {
+        let commit = repo.find_commit(gix::ObjectId::from_hex(commit.as_bytes())?)?;
+        if path.is_empty() {
+            return Ok(repo.find_object(commit.tree_id()?)?);
+        }
+        Ok(commit
+            .tree()?
+            .lookup_entry_by_path(path)?
+            .with_context(|| format!("{path} does not exist in {}", commit.id))?
+            .object()?)
+    }
+
+    fn list_dir_inner(&self, commit: &str, dir: &str) -> anyhow::Result<Vec<DirEntry>> {
+        let repo = self.open()?;
+        let tree = Self::find_in_commit(&repo, commit, dir)?.try_into_tree()?;
+        let mut entries = Vec::new();
+        for entry in tree.iter() {
+            let entry = entry?;
+            entries.push(DirEntry {
+                name: entry.filename().to_string(),
+                is_dir: entry.mode().is_tree(),
+            });
+        }
+        Ok(entries)
+    }
+
+    fn read_file_inner(&self, commit: &str, path: &str) -> anyhow::Result<String> {
+        let repo = self.open()?;
+        let blob = Self::find_in_commit(&repo, commit, path)?.try_into_blob()?;
+        Ok(String::from_utf8(blob.detach().data)?)
+    }
+
+    fn discard_unstaged_inner(&self) -> anyhow::Result<()> {
+        let repo = self.open()?;
+        Self::checkout(&repo, repo.open_index()?)
+    }
+
+    fn add_inner(&self, pathspec: &str) -> anyhow::Result<()> {
+        let repo = self.open()?;
+        let workdir = repo.workdir().context("should not be bare")?.to_owned();
+        let mut index = repo.open_index()?;
+        let prefix = format!("{pathspec}/");
+        index.remove_entries(|_, path, _| path == pathspec || path.starts_with(prefix.as_bytes()));
+
+        let mut pending = vec![workdir.join(pathspec)];
+        while let Some(path) = pending.pop() {
+            // deleted paths only need their entries removed
+            let Ok(metadata) = gix::index::fs::Metadata::from_path_no_follow(&path) else {
+                continue;
+            };
+            if metadata.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    pending.push(entry?.path());
+                }
+                continue;
+            }
+            // like git the target of a symlink is stored instead of the content it points to
+            let (content, mode) = if metadata.is_symlink() {
+                let target = gix::path::into_bstr(fs::read_link(&path)?)?.into_owned();
+                (target.into(), gix::index::entry::Mode::SYMLINK)
+            } else if metadata.is_file() {
+                let mode = if metadata.is_executable() {
+                    gix::index::entry::Mode::FILE_EXECUTABLE
+                } else {
+                    gix::index::entry::Mode::FILE
+                };
+                (fs::read(&path)?, mode)
+            } else {
+                continue;
+            };
+            let id = repo.write_blob(content)?.detach();
+            let relative = gix::path::into_bstr(path.strip_prefix(&workdir)?)?;
+            index.dangerously_push_entry(
+                gix::index::entry::Stat::from_fs(&metadata)?,
+                id,
+                gix::index::entry::Flags::empty(),
+                mode,
+                relative.as_ref(),
+            );
+        }
+        index.sort_entries();
+        index.write(gix::index::write::Options::default())?;
+        Ok(())
+    }
+
+    /// Write the tree of the index and return it together with the tree of HEAD
+    fn index_tree(repo: &gix::Repository) -> anyhow::Result<(gix::ObjectId, gix::ObjectId)> {
+        let index = repo.open_index()?;
+        let mut editor = repo.edit_tree(gix::ObjectId::empty_tree(repo.object_hash()))?;
+        for entry in index.entries() {
+            let path = entry.path(&index);
+            let mode = entry
+                .mode
+                .to_tree_entry_mode()
+                .with_context(|| format!("unknown mode of {path} in the index"))?;
+            editor.upsert(path, mode.kind(), entry.id)?;
+        }
+        let tree = editor.write()?.detach();
+        let head = repo.head_commit()?.tree_id()?.detach();
+        Ok((tree, head))
+    }
+
+    fn has_staged_changes_inner(&self) -> anyhow::Result<bool> {
+        let (tree, head) = Self::index_tree(&self.open()?)?;
+        Ok(tree != head)
+    }
+
+    fn commit_inner(&self, message: &str) -> anyhow::Result<()> {
+        let repo = self.open()?;
+        let (tree, head_tree) = Self::index_tree(&repo)?;
+        anyhow::ensure!(tree != head_tree, "nothing to commit");
+        let head = repo.head_commit()?;
+
+        let time = gix::date::Time::now_local_or_utc().to_string();
+        let author = gix::actor::SignatureRef {
+            name: self.config.author_name.as_str().into(),
+            email: self.config.author_email.as_str().into(),
+            time: &time,
+        };
+        let committer = repo.committer().context("should have a committer")??;
+        repo.commit_as(committer, author, "HEAD", message, tree, [head.id])?;
+        Ok(())
+    }
+
+    fn push_inner(&self) -> anyhow::Result<()> {
+        let repo = self.open()?;
+        let head = repo.head_id()?.detach();
+        let branch = repo.head_name()?.context("HEAD should be a branch")?;
+        let remote = repo
+            .find_default_remote(Direction::Push)
+            .context("should have a remote")??;
+        let (url, _) = remote.sanitized_url_and_version(Direction::Push)?;
+        let ssh = if url.scheme == gix::url::Scheme::Ssh {
+            repo.ssh_connect_options()?
+        } else {
+            ssh::connect::Options::default()
+        };
+        let mut transport = connect::connect(
+            url,
+            connect::Options {
+                version: gix::protocol::transport::Protocol::V1,
+                ssh,
+                trace: false,
+            },
+        )?;
+
+        let remote_head = {
+            let response =
+                transport.handshake(gix::protocol::transport::Service::ReceivePack, &[])?;
+            let mut remote_head = gix::ObjectId::null(repo.object_hash());
+            if let Some(mut refs) = response.refs {
+                let mut line = String::new();
+                while refs.readline_str(&mut line)? > 0 {
+                    let (id, name) = line
+                        .trim_end()
+                        .split_once(' ')
+                        .with_context(|| format!("unexpected ref advertisement {line:?}"))?;
+                    if name == branch.as_bstr() {
+                        remote_head = gix::ObjectId::from_hex(id.as_bytes())?;
+                    }
+                    line.clear();
+                }
+            }
+            remote_head
+        };
+        if remote_head == head {
+            return Ok(());
+        }
+
+        let mut walk = repo.rev_walk([head]);
+        if !remote_head.is_null() {
+            if !repo.has_object(remote_head) {
+                return Err(Rejected("fetch first".to_owned()).into());
+            }
+            walk = walk.with_hidden([remote_head]);
+        }
+        let commits = walk
+            .all()?
+            .map(|info| info.map(|info| info.id))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut request = transport.request(WriteMode::Binary, MessageKind::Flush, false)?;
+        let command = format!("{remote_head} {head} {}\0report-status\n", branch.as_bstr());
+        request.write_all(command.as_bytes())?;
+        request.write_message(MessageKind::Flush)?;
+        let (mut writer, mut reader) = request.into_parts();
+        write_pack(&repo, commits, &mut writer)?;
+        writer.flush()?;
+        // the remote only answers once the pack is complete
+        drop(writer);
+
+        let mut report = Vec::new();
+        let mut line = String::new();
+        while reader.readline_str(&mut line)? > 0 {
+            report.push(line.trim_end().to_owned());
+            line.clear();
+        }
+        let expected = format!("ok {}", branch.as_bstr());
+        let rejected = format!("ng {} ", branch.as_bstr());
+        if let Some(line) = report.iter().find(|line| line.starts_with(&rejected))
+            && (line.ends_with("non-fast-forward") || line.ends_with("fetch first"))
+        {
+            return Err(Rejected(line[rejected.len()..].to_owned()).into());
+        }
+        anyhow::ensure!(
+            report.first().is_some_and(|line| line == "unpack ok") && report.contains(&expected),
+            "push rejected: {}",
+            report.join(", ")
+        );
+        Ok(())
+    }
+}
+
+/// Write a pack with the commits and the objects they added compared to their parents
+fn write_pack(
+    repo: &gix::Repository,
+    commits: Vec<gix::ObjectId>,
+    writer: &mut dyn std::io::Write,
+) -> anyhow::Result<()> {
+    let interrupt = AtomicBool::new(false);
+    let mut db = repo.objects.clone().into_arc()?.into_inner();
+    db.prevent_pack_unload();
+    let (counts, _) = output::count::objects(
+        db.clone(),
+        Box::new(commits.into_iter().map(Ok)),
+        &Discard,
+        &interrupt,
+        output::count::objects::Options {
+            thread_limit: Some(1),
+            input_object_expansion:
+                output::count::objects::ObjectExpansion::TreeAdditionsComparedToAncestor,
+            ..Default::default()
+        },
+    )?;
+    let amount = u32::try_from(counts.len())?;
+    let entries = output::entry::iter_from_counts(
+        counts,
+        db,
+        Box::new(Discard),
+        output::entry::iter_from_counts::Options {
+            thread_limit: Some(1),
+            ..Default::default()
+        },
+    )?;
+
+    let pack = output::bytes::FromEntriesIter::new(
+        entries.map(|chunk| chunk.map(|(_, entries)| entries)),
+        writer,
+        amount,
+        gix_pack::data::Version::V2,
+        repo.object_hash(),
+    );
+    for written in pack {
+        written?;
+    }
+    Ok(())
+}
+
+impl GitRepo for Gix {
+    fn exists(&self) -> bool {
+        self.path.join(".git").exists()
+    }
+
+    fn clone_remote(&self) -> Result<(), GitError> {
+        wrap("clone", self.clone_inner())
+    }
+
+    fn pull(&self) -> Result<(), GitError> {
+        let (remote, branch) = wrap("pull", self.checked_out())?;
+        check_checkout(&self.config, &remote, &branch)?;
+        wrap("pull", self.pull_inner())
+    }
+
+    fn add(&self, pathspec: &str) -> Result<(), GitError> {
+        wrap("add", self.add_inner(pathspec))
+    }
+
+    fn has_staged_changes(&self) -> Result<bool, GitError> {
+        wrap("diff", self.has_staged_changes_inner())
+    }
+
+    fn commit(&self, message: &str) -> Result<(), GitError> {
+        wrap("commit", self.commit_inner(message))
+    }
+
+    fn push(&self) -> Result<(), GitError> {
+        self.push_inner()
+            .map_err(|source| match source.downcast::<Rejected>() {
+                Ok(Rejected(reason)) => GitError::Rejected(reason),
+                Err(source) => GitError::Gix {
+                    operation: "push",
+                    source,
+                },
+            })
+    }
+
+    fn reset_to_upstream(&self) -> Result<(), GitError> {
+        wrap("reset", self.reset_to_upstream_inner())
+    }
+
+    fn discard_unstaged(&self) -> Result<(), GitError> {
+        wrap("checkout", self.discard_unstaged_inner())
+    }
+
+    fn commit_before(&self, time: DateTime<Utc>) -> Result<Option<String>, GitError> {
+        wrap("rev-list", self.commit_before_inner(time))
+    }
+
+    fn list_dir(&self, commit: &str, dir: &str) -> Result<Vec<DirEntry>, GitError> {
+        wrap("ls-tree", self.list_dir_inner(commit, dir))
+    }
+
+    fn read_file(&self, commit: &str, path: &str) -> Result<String, GitError> {
+        wrap("show", self.read_file_inner(commit, path))
+    }
+}
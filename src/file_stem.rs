@@ -0,0 +1,272 @@
+//! Strategies to name the files of the downloaded ICS files as every source has its own url scheme
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use anyhow::Context as _;
+use lazy_regex::Regex;
+use serde::Deserialize;
+
+use crate::ics_urls::Link;
+use crate::{files, manifest};
+
+pub trait FileStemStrategy: Debug {
+    /// The file stem known from the link alone which allows to detect collisions before downloading.
+    ///
+    /// Returns `None` when the file stem depends on the content.
+    fn by_link(&self, link: &Link) -> anyhow::Result<Option<String>>;
+
+    /// The file stem depending on the downloaded ICS content.
+    /// Only used when [`Self::by_link`] returned `None`.
+    fn by_content(&self, _link: &Link, _ics: &str) -> anyhow::Result<String> {
+        anyhow::bail!("file stem should be known from the link")
+    }
+}
+
+/// How the strategies are configured in the config file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+enum Config {
+    LastPathSegment,
+    QueryParameter(String),
+    Regex(String),
+    LinkText,
+    ContentHash,
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn FileStemStrategy>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let strategy: Box<dyn FileStemStrategy> = match Config::deserialize(deserializer)? {
+        Config::LastPathSegment => Box::new(LastPathSegment),
+        Config::QueryParameter(name) => Box::new(QueryParameter(name)),
+        Config::Regex(pattern) => Box::new(UrlRegex(
+            Regex::new(&pattern).map_err(serde::de::Error::custom)?,
+        )),
+        Config::LinkText => Box::new(LinkText),
+        Config::ContentHash => Box::new(ContentHash),
+    };
+    Ok(strategy)
+}
+
+pub fn default() -> Box<dyn FileStemStrategy> {
+    Box::new(LastPathSegment)
+}
+
+/// The sanitized file stems of the links which are known before downloading.
+///
+/// Links of the same base whose file stems collide get a short hash of their url appended.
+pub fn for_links(
+    strategy: &dyn FileStemStrategy,
+    links: &[Link],
+) -> Vec<anyhow::Result<Option<String>>> {
+    let mut file_stems = links
+        .iter()
+        .map(|link| {
+            let file_stem = strategy.by_link(link)?;
+            file_stem.as_deref().map(files::sanitize_name).transpose()
+        })
+        .collect::<Vec<_>>();
+
+    let mut amount: HashMap<String, usize> = HashMap::new();
+    for file_stem in file_stems.iter().flatten().flatten() {
+        *amount.entry(file_stem.clone()).or_default() += 1;
+    }
+
+    for (link, file_stem) in links.iter().zip(&mut file_stems) {
+        if let Ok(Some(file_stem)) = file_stem
+            && amount[file_stem.as_str()] > 1
+        {
+            disambiguate(file_stem, link);
+        }
+    }
+
+    file_stems
+}
+
+/// Append a short hash of the url to the colliding file stem
+pub fn disambiguate(file_stem: &mut String, link: &Link) {
+    let url_hash = manifest::hash(link.url.as_str());
+    let disambiguated = format!("{file_stem}-{}", &url_hash[..8]);
+    println!(
+        "WARNING: file stem collision {file_stem} renamed to {disambiguated} for {}",
+        link.url
+    );
+    *file_stem = disambiguated;
+}
+
+fn without_ics_extension(name: &str) -> &str {
+    let split = name.len().saturating_sub(4);
+    match name.get(split..) {
+        Some(extension) if split > 0 && extension.eq_ignore_ascii_case(".ics") => &name[..split],
+        _ => name,
+    }
+}
+
+/// Last path segment of the url without the extension
+#[derive(Debug)]
+struct LastPathSegment;
+
+impl FileStemStrategy for LastPathSegment {
+    fn by_link(&self, link: &Link) -> anyhow::Result<Option<String>> {
+        let filename = link
+            .url
+            .path_segments()
+            .context("ICS url should always have a path")?
+            .next_back()
+            .context("path_segments should always return at least one segment")?;
+        let (filestem, _) = filename
+            .rsplit_once('.')
+            .context("should always contain a .")?;
+        Ok(Some(filestem.to_owned()))
+    }
+}
+
+/// Value of a query parameter without the `.ics` extension
+#[derive(Debug)]
+struct QueryParameter(String);
+
+impl FileStemStrategy for QueryParameter {
+    fn by_link(&self, link: &Link) -> anyhow::Result<Option<String>> {
+        let (_, value) = link
+            .url
+            .query_pairs()
+            .find(|(name, _)| name == self.0.as_str())
+            .with_context(|| format!("should have the query parameter {}", self.0))?;
+        Ok(Some(without_ics_extension(&value).to_owned()))
+    }
+}
+
+/// First participating capture group of the regex over the url
+#[derive(Debug)]
+struct UrlRegex(Regex);
+
+impl FileStemStrategy for UrlRegex {
+    fn by_link(&self, link: &Link) -> anyhow::Result<Option<String>> {
+        let captures = self
+            .0
+            .captures(link.url.as_str())
+            .with_context(|| format!("should match the regex {}", self.0))?;
+        let filestem = captures
+            .iter()
+            .skip(1)
+            .flatten()
+            .next()
+            .context("regex should contain a capture group")?;
+        Ok(Some(filestem.as_str().to_owned()))
+    }
+}
+
+/// Text of the link without the `.ics` extension
+#[derive(Debug)]
+struct LinkText;
+
+impl FileStemStrategy for LinkText {
+    fn by_link(&self, link: &Link) -> anyhow::Result<Option<String>> {
+        anyhow::ensure!(!link.text.is_empty(), "link should have a text");
+        Ok(Some(without_ics_extension(&link.text).to_owned()))
+    }
+}
+
+/// Hash of the ICS content for urls which contain nothing useful
+#[derive(Debug)]
+struct ContentHash;
+
+impl FileStemStrategy for ContentHash {
+    fn by_link(&self, _link: &Link) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn by_content(&self, _link: &Link, ics: &str) -> anyhow::Result<String> {
+        let mut hash = manifest::hash(ics);
+        hash.truncate(16);
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+fn link(url: &str, text: &str) -> Link {
+    Link {
+        url: url::Url::parse(url).unwrap(),
+        text: text.to_owned(),
+    }
+}
+
+#[test]
+fn last_path_segment_strategy() -> anyhow::Result<()> {
+    let link = link("https://www.haw-hamburg.de/files/B-AI1.ics?v=2", "AI 1");
+    assert_eq!(LastPathSegment.by_link(&link)?.unwrap(), "B-AI1");
+    Ok(())
+}
+
+#[test]
+fn query_parameter_strategy() -> anyhow::Result<()> {
+    let link = link("https://example.org/download?id=42&file=Plan%20WS.ics", "");
+    assert_eq!(
+        QueryParameter("file".to_owned()).by_link(&link)?.unwrap(),
+        "Plan WS"
+    );
+    Ok(())
+}
+
+#[test]
+fn regex_strategy_uses_first_participating_group() -> anyhow::Result<()> {
+    let strategy = UrlRegex(Regex::new(r"media=stundenplan:(.+)\.ics|/([^/?]+)\.ics$")?);
+    let fetch = link(
+        "https://userdoc.informatik.haw-hamburg.de/lib/exe/fetch.php?media=stundenplan:ws_inf.ics",
+        "",
+    );
+    assert_eq!(strategy.by_link(&fetch)?.unwrap(), "ws_inf");
+    let direct = link(
+        "https://userdoc.informatik.haw-hamburg.de/files/ss_inf.ics",
+        "",
+    );
+    assert_eq!(strategy.by_link(&direct)?.unwrap(), "ss_inf");
+    Ok(())
+}
+
+#[test]
+fn link_text_strategy() -> anyhow::Result<()> {
+    let link = link("https://example.org/export?id=1", "Medientechnik 3.ics");
+    assert_eq!(LinkText.by_link(&link)?.unwrap(), "Medientechnik 3");
+    Ok(())
+}
+
+#[test]
+fn content_hash_strategy() -> anyhow::Result<()> {
+    let link = link("https://example.org/export?id=1", "");
+    assert_eq!(ContentHash.by_link(&link)?, None);
+    assert_eq!(ContentHash.by_content(&link, "")?, "e3b0c44298fc1c14");
+    Ok(())
+}
+
+#[test]
+fn colliding_file_stems_get_url_hash() {
+    let links = [
+        link("https://www.haw-hamburg.de/ws/plan.ics", ""),
+        link("https://www.haw-hamburg.de/ss/plan.ics", ""),
+        link("https://www.haw-hamburg.de/ss/other.ics", ""),
+        link("https://www.haw-hamburg.de/ss/broken", ""),
+    ];
+    let file_stems = for_links(&LastPathSegment, &links)
+        .into_iter()
+        .map(|file_stem| file_stem.ok().flatten())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        file_stems,
+        [
+            Some(format!(
+                "plan-{}",
+                &manifest::hash(links[0].url.as_str())[..8]
+            )),
+            Some(format!(
+                "plan-{}",
+                &manifest::hash(links[1].url.as_str())[..8]
+            )),
+            Some("other".to_owned()),
+            None,
+        ]
+    );
+    assert_ne!(file_stems[0], file_stems[1]);
+}
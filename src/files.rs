@@ -1,15 +1,316 @@
+use std::collections::BTreeSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use percent_encoding::percent_decode_str;
 use serde::Serialize;
 use serde_json::Serializer;
 use serde_json::ser::PrettyFormatter;
+use unicode_normalization::UnicodeNormalization as _;
 
-pub fn save_to_json<P: AsRef<Path>, T: Serialize>(path: P, contents: &T) {
+use crate::manifest;
+
+/// Maximum bytes of a sanitized name leaving room for extensions within the common limit of 255 bytes
+const MAX_NAME_LENGTH: usize = 200;
+
+/// Tab indented JSON with a final newline like all the committed files
+pub fn to_json<T: Serialize>(contents: &T) -> Vec<u8> {
     let mut bytes = Vec::new();
     let formatter = PrettyFormatter::with_indent(b"\t");
     let mut ser = Serializer::with_formatter(&mut bytes, formatter);
     contents.serialize(&mut ser).expect("serialize to json");
     bytes.push(b'\n'); // final newline
-    fs::write(path, bytes).expect("write file");
+    bytes
+}
+
+pub fn save_to_json<P: AsRef<Path>, T: Serialize>(path: P, contents: &T) {
+    fs::write(path, to_json(contents)).expect("write file");
+}
+
+/// Where [`swap_dir`] moves the target aside
+fn swapped_out(staged: &Path) -> PathBuf {
+    let mut old = staged.as_os_str().to_owned();
+    old.push(".old");
+    PathBuf::from(old)
+}
+
+/// Replace the target directory with the completely staged one.
+///
+/// The target is moved aside and the staged directory renamed into its place.
+/// Each rename is atomic but between them the target does not exist.
+/// When interrupted there the old version is left next to the staged directory, see [`restore_swapped_out`].
+/// Both directories have to be on the same file system.
+pub fn swap_dir(staged: &Path, target: &Path) -> std::io::Result<()> {
+    let old = swapped_out(staged);
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    if target.exists() {
+        fs::rename(target, &old)?;
+    }
+    fs::rename(staged, target)?;
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+/// Clean up after every interrupted [`swap_dir`] whose staged directory is within the staging root.
+///
+/// The target of `<staging_root>/<path>` is `<target_root>/<path>`.
+/// Old versions are moved back into place when the staged one was not renamed yet and removed otherwise.
+pub fn restore_swapped_out(staging_root: &Path, target_root: &Path) -> std::io::Result<()> {
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let path = staging_root.join(&relative);
+        if !path.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let file_name = entry.file_name();
+            let Some(name) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".old"))
+            else {
+                pending.push(relative.join(file_name));
+                continue;
+            };
+            let old = entry.path();
+            let target = target_root.join(&relative).join(name);
+            if target.exists() {
+                fs::remove_dir_all(&old)?;
+            } else {
+                println!(
+                    "WARNING: restore {} of an interrupted swap",
+                    target.display()
+                );
+                fs::rename(&old, target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy the directory recursively. Git metadata is skipped.
+pub fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Names of the JSON files directly within the directory. A missing directory has none.
+pub fn json_files(dir: &Path) -> std::io::Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    if !dir.exists() {
+        return Ok(names);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_json = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension == "json");
+        if is_json && let Some(name) = path.file_name() {
+            names.insert(name.to_string_lossy().into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// Paths of all files within the directory relative to it. Git metadata is skipped.
+pub fn list_files(dir: &Path) -> std::io::Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let path = dir.join(&relative);
+        if !path.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let relative = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(relative);
+            } else {
+                files.insert(relative);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Files which differ between the directories prefixed like `git status --short` does.
+///
+/// A for added, M for changed and D for removed files.
+pub fn diff_dirs(before: &Path, after: &Path) -> std::io::Result<Vec<(char, PathBuf)>> {
+    let before_files = list_files(before)?;
+    let after_files = list_files(after)?;
+    let mut changes = Vec::new();
+    for path in before_files.union(&after_files) {
+        let status = match (before_files.contains(path), after_files.contains(path)) {
+            (true, true) if fs::read(before.join(path))? == fs::read(after.join(path))? => {
+                continue;
+            }
+            (true, true) => 'M',
+            (false, _) => 'A',
+            (true, false) => 'D',
+        };
+        changes.push((status, path.clone()));
+    }
+    Ok(changes)
+}
+
+/// Turn a name from an url or an event into a file name which is safe to join onto a folder.
+///
+/// Percent-encoded sequences are decoded, the name is NFC normalised, separators and control characters are replaced and leading dots are removed.
+/// Overly long names are shortened and get a hash of the full name appended to keep them distinct.
+pub fn sanitize_name(name: &str) -> anyhow::Result<String> {
+    let decoded = percent_decode_str(name).decode_utf8_lossy();
+    let replaced = decoded
+        .nfc()
+        .map(|char| {
+            if matches!(char, '/' | '\\') || char.is_control() {
+                '-'
+            } else {
+                char
+            }
+        })
+        .collect::<String>();
+    let safe = replaced.trim().trim_start_matches('.').trim_start();
+    anyhow::ensure!(!safe.is_empty(), "name {name:?} is not usable as file name");
+
+    if safe.len() <= MAX_NAME_LENGTH {
+        return Ok(safe.to_owned());
+    }
+    let hash = manifest::hash(safe);
+    let mut end = MAX_NAME_LENGTH - 9;
+    while !safe.is_char_boundary(end) {
+        end -= 1;
+    }
+    Ok(format!("{}-{}", &safe[..end], &hash[..8]))
+}
+
+#[test]
+fn sanitize_keeps_usual_names() -> anyhow::Result<()> {
+    assert_eq!(
+        sanitize_name("BAI1-SE1 Softwareentwicklung 1")?,
+        "BAI1-SE1 Softwareentwicklung 1"
+    );
+    assert_eq!(sanitize_name("Mathe 1/2")?, "Mathe 1-2");
+    Ok(())
+}
+
+#[test]
+fn sanitize_refuses_traversal() {
+    assert!(sanitize_name("..").is_err());
+    assert!(sanitize_name("%2e%2E").is_err());
+    assert!(sanitize_name(" ").is_err());
+    assert_eq!(sanitize_name("../../etc/passwd").unwrap(), "-..-etc-passwd");
+    assert_eq!(sanitize_name("..%2F..%5Cevil").unwrap(), "-..-evil");
+    assert_eq!(sanitize_name("nul\0byte").unwrap(), "nul-byte");
+}
+
+#[test]
+fn sanitize_normalizes_unicode() -> anyhow::Result<()> {
+    let composed = sanitize_name("Pr\u{fc}fung")?;
+    let decomposed = sanitize_name("Pru\u{308}fung")?;
+    assert_eq!(composed, decomposed);
+    assert_eq!(sanitize_name("Pr%C3%BCfung")?, composed);
+    Ok(())
+}
+
+#[test]
+fn sanitize_limits_length() -> anyhow::Result<()> {
+    let long = "ä".repeat(150);
+    let sanitized = sanitize_name(&long)?;
+    assert!(sanitized.len() <= MAX_NAME_LENGTH);
+    assert_ne!(sanitized, sanitize_name(&"ä".repeat(151))?);
+    Ok(())
+}
+
+#[test]
+fn swap_dir_replaces_target() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let staged = dir.path().join("staging/informatik");
+    let target = dir.path().join("events/informatik");
+    fs::create_dir_all(&staged)?;
+    fs::create_dir_all(&target)?;
+    fs::write(staged.join("new.json"), "[]")?;
+    fs::write(target.join("outdated.json"), "[]")?;
+
+    swap_dir(&staged, &target)?;
+    assert!(target.join("new.json").exists());
+    assert!(!target.join("outdated.json").exists());
+    assert!(!staged.exists());
+    assert!(!dir.path().join("staging/informatik.old").exists());
+    Ok(())
+}
+
+#[test]
+fn interrupted_swap_is_restored() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let staging = dir.path().join("events/.staging");
+    let target = dir.path().join("events/metadata/informatik");
+    let old = staging.join("metadata/informatik.old");
+    fs::create_dir_all(&old)?;
+    fs::create_dir_all(target.parent().unwrap())?;
+    fs::write(old.join("committed.json"), "[]")?;
+    // the swap of this base completed before the removal of the old version
+    fs::create_dir_all(staging.join("mathe.old"))?;
+    fs::create_dir_all(dir.path().join("events/mathe"))?;
+
+    restore_swapped_out(&staging, &dir.path().join("events"))?;
+    assert!(target.join("committed.json").exists());
+    assert!(!old.exists());
+    assert!(!staging.join("mathe.old").exists());
+    assert!(dir.path().join("events/mathe").exists());
+    Ok(())
+}
+
+#[test]
+fn copy_and_diff_dirs() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let before = dir.path().join("before");
+    let after = dir.path().join("after");
+    fs::create_dir_all(before.join(".git"))?;
+    fs::create_dir_all(before.join("informatik"))?;
+    fs::write(before.join(".git/HEAD"), "ref")?;
+    fs::write(before.join("informatik/same.json"), "[]")?;
+    fs::write(before.join("informatik/changed.json"), "[]")?;
+    fs::write(before.join("removed.json"), "[]")?;
+
+    copy_dir(&before, &after)?;
+    assert!(!after.join(".git").exists());
+    assert!(diff_dirs(&before, &after)?.is_empty());
+
+    fs::write(after.join("informatik/changed.json"), "[{}]")?;
+    fs::remove_file(after.join("removed.json"))?;
+    fs::write(after.join("informatik/added.json"), "[]")?;
+    assert_eq!(
+        diff_dirs(&before, &after)?,
+        [
+            ('A', PathBuf::from("informatik/added.json")),
+            ('M', PathBuf::from("informatik/changed.json")),
+            ('D', PathBuf::from("removed.json")),
+        ]
+    );
+    Ok(())
 }
@@ -0,0 +1,187 @@
+//! Health of the sources and their ICS urls across runs to tell lasting failures from one-off hiccups
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::config::Source;
+use crate::{events_git, files};
+
+/// Committed with the events. Only days are tracked to not change the file on every run.
+pub const FILE: &str = "health.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    pub last_success: Option<NaiveDate>,
+    pub last_failure: Option<NaiveDate>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SourceHealth {
+    #[serde(flatten)]
+    pub record: Record,
+    /// Records of the ICS urls discovered in the last successful run
+    pub urls: BTreeMap<String, Record>,
+}
+
+#[derive(Debug)]
+pub struct Health {
+    today: NaiveDate,
+    sources: BTreeMap<String, SourceHealth>,
+}
+
+impl Record {
+    const fn succeeded(&mut self, today: NaiveDate) {
+        self.last_success = Some(today);
+        self.consecutive_failures = 0;
+    }
+
+    fn failed(&mut self, today: NaiveDate, err: &anyhow::Error) {
+        self.last_failure = Some(today);
+        self.consecutive_failures += 1;
+        self.last_error = Some(format!("{err:#}"));
+    }
+
+    const fn is_degraded(&self) -> bool {
+        self.consecutive_failures > 0
+    }
+
+    fn describe(&self) -> String {
+        let last_success = self
+            .last_success
+            .map_or_else(|| "never".to_owned(), |date| date.to_string());
+        format!(
+            "{} consecutive failures, last success {last_success}, last error: {}",
+            self.consecutive_failures,
+            self.last_error.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl Health {
+    /// Load the health of the last runs. Missing or broken files are treated as empty.
+    pub fn load() -> Self {
+        let path = Path::new(events_git::FOLDER).join(FILE);
+        let mut health = Self {
+            today: Utc::now().date_naive(),
+            sources: BTreeMap::new(),
+        };
+        let Ok(content) = fs::read_to_string(&path) else {
+            return health;
+        };
+        match serde_json::from_str(&content) {
+            Ok(sources) => health.sources = sources,
+            Err(err) => println!("WARNING: ignore broken {}: {err}", path.display()),
+        }
+        health
+    }
+
+    pub fn save(&self) {
+        files::save_to_json(Path::new(events_git::FOLDER).join(FILE), &self.sources);
+    }
+
+    /// The base was updated. Records of urls which were not discovered anymore are dropped.
+    pub fn source_succeeded(&mut self, base: &str, urls: &[&Url]) {
+        let source = self.sources.entry(base.to_owned()).or_default();
+        source.record.succeeded(self.today);
+        source
+            .urls
+            .retain(|url, _| urls.iter().any(|expected| expected.as_str() == url));
+    }
+
+    pub fn source_failed(&mut self, base: &str, err: &anyhow::Error) {
+        self.sources
+            .entry(base.to_owned())
+            .or_default()
+            .record
+            .failed(self.today, err);
+    }
+
+    pub fn url_succeeded(&mut self, base: &str, url: &Url) {
+        let today = self.today;
+        self.url_record(base, url).succeeded(today);
+    }
+
+    pub fn url_failed(&mut self, base: &str, url: &Url, err: &anyhow::Error) {
+        let today = self.today;
+        self.url_record(base, url).failed(today, err);
+    }
+
+    fn url_record(&mut self, base: &str, url: &Url) -> &mut Record {
+        self.sources
+            .entry(base.to_owned())
+            .or_default()
+            .urls
+            .entry(url.to_string())
+            .or_default()
+    }
+
+    /// Drop the records of sources which are not configured anymore
+    pub fn retain_sources(&mut self, sources: &[Source]) {
+        self.sources
+            .retain(|base, _| sources.iter().any(|source| source.base == *base));
+    }
+
+    /// Human readable lines about the degraded sources and urls
+    pub fn degraded(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for (base, source) in &self.sources {
+            let urls = source
+                .urls
+                .iter()
+                .filter(|(_, record)| record.is_degraded())
+                .collect::<Vec<_>>();
+            if !source.record.is_degraded() && urls.is_empty() {
+                continue;
+            }
+            if source.record.is_degraded() {
+                lines.push(format!("{base}: {}", source.record.describe()));
+            } else {
+                lines.push(format!("{base}: {} failing urls", urls.len()));
+            }
+            for (url, record) in urls {
+                lines.push(format!("  {url}: {}", record.describe()));
+            }
+        }
+        lines
+    }
+}
+
+#[test]
+fn failures_are_counted_until_success() {
+    let day = |day| NaiveDate::from_ymd_opt(2026, 10, day).unwrap();
+    let url = Url::parse("https://example.org/a.ics").unwrap();
+    let gone = Url::parse("https://example.org/gone.ics").unwrap();
+    let mut health = Health {
+        today: day(1),
+        sources: BTreeMap::new(),
+    };
+    health.url_succeeded("informatik", &url);
+    health.url_failed("informatik", &gone, &anyhow::anyhow!("404"));
+    health.source_succeeded("informatik", &[&url, &gone]);
+    health.today = day(2);
+    health.source_failed("informatik", &anyhow::anyhow!("no ics urls found"));
+    health.today = day(3);
+    health.source_failed("informatik", &anyhow::anyhow!("no ics urls found"));
+
+    assert_eq!(
+        health.degraded(),
+        [
+            "informatik: 2 consecutive failures, last success 2026-10-01, last error: no ics urls found",
+            "  https://example.org/gone.ics: 1 consecutive failures, last success never, last error: 404",
+        ]
+    );
+
+    health.today = day(4);
+    health.url_succeeded("informatik", &url);
+    health.source_succeeded("informatik", &[&url]);
+    assert!(health.degraded().is_empty());
+    assert_eq!(health.sources["informatik"].urls.len(), 1);
+}
@@ -0,0 +1,134 @@
+//! Reconstruct the events of a past day from the history of the events repo
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use chrono::{DateTime, NaiveDate, TimeZone as _, Utc};
+use chrono_tz::Europe::Berlin;
+
+use crate::events_git::GitRepo;
+use crate::{EventEntry, metadata};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// All events of a base
+    Base(String),
+    /// Events of one file of a base
+    File { base: String, file: String },
+    /// Events with this name in any base
+    Name(String),
+}
+
+impl Selection {
+    /// `base`, `base/file` with or without `.json` or `--name <event name>`
+    pub fn parse(args: &[String]) -> anyhow::Result<Self> {
+        match args {
+            [flag, name] if flag == "--name" => Ok(Self::Name(name.clone())),
+            [path] => Ok(match path.split_once('/') {
+                Some((base, file)) => Self::File {
+                    base: base.to_owned(),
+                    file: if is_json(file) {
+                        file.to_owned()
+                    } else {
+                        format!("{file}.json")
+                    },
+                },
+                None => Self::Base(path.clone()),
+            }),
+            _ => anyhow::bail!("expected <base>, <base>/<file> or --name <event name>"),
+        }
+    }
+}
+
+fn is_json(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+/// End of the day in Berlin
+pub fn end_of_day(date: NaiveDate) -> anyhow::Result<DateTime<Utc>> {
+    let end = date
+        .succ_opt()
+        .context("date is too far in the future")?
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time");
+    let end = Berlin
+        .from_local_datetime(&end)
+        .earliest()
+        .context("midnight should exist in Berlin")?;
+    Ok(end.to_utc() - chrono::Duration::seconds(1))
+}
+
+/// Events selected from the last commit of the date
+pub fn events_at(
+    repo: &dyn GitRepo,
+    date: NaiveDate,
+    selection: &Selection,
+) -> anyhow::Result<Vec<EventEntry>> {
+    let commit = repo.commit_before(end_of_day(date)?)?.with_context(|| {
+        format!(
+            "the events history does not reach back to {date}, depth = 0 in [git] clones all of it"
+        )
+    })?;
+    eprintln!("Events as of commit {commit}");
+
+    let read = |path: &str| -> anyhow::Result<Vec<EventEntry>> {
+        let content = repo.read_file(&commit, path)?;
+        serde_json::from_str(&content).with_context(|| format!("parse {path}"))
+    };
+    let read_base = |base: &str| -> anyhow::Result<Vec<EventEntry>> {
+        let mut events = Vec::new();
+        for entry in repo.list_dir(&commit, base)? {
+            if !entry.is_dir && is_json(&entry.name) {
+                events.append(&mut read(&format!("{base}/{}", entry.name))?);
+            }
+        }
+        Ok(events)
+    };
+
+    match selection {
+        Selection::File { base, file } => read(&format!("{base}/{file}")),
+        Selection::Base(base) => read_base(base),
+        Selection::Name(name) => {
+            let mut events = Vec::new();
+            for entry in repo.list_dir(&commit, "")? {
+                if entry.is_dir && !entry.name.starts_with('.') && entry.name != metadata::FOLDER {
+                    events.append(&mut read_base(&entry.name)?);
+                }
+            }
+            events.retain(|event| &event.name == name);
+            Ok(events)
+        }
+    }
+}
+
+#[test]
+fn selection_parsing() {
+    let parse = |args: &[&str]| {
+        let args = args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>();
+        Selection::parse(&args)
+    };
+    assert_eq!(
+        parse(&["informatik"]).unwrap(),
+        Selection::Base("informatik".to_owned())
+    );
+    let file = Selection::File {
+        base: "informatik".to_owned(),
+        file: "BAI1-SE1.json".to_owned(),
+    };
+    assert_eq!(parse(&["informatik/BAI1-SE1"]).unwrap(), file);
+    assert_eq!(parse(&["informatik/BAI1-SE1.json"]).unwrap(), file);
+    assert_eq!(
+        parse(&["--name", "BAI1-SE1 Softwareentwicklung 1"]).unwrap(),
+        Selection::Name("BAI1-SE1 Softwareentwicklung 1".to_owned())
+    );
+    assert!(parse(&[]).is_err());
+}
+
+#[test]
+fn end_of_day_is_in_berlin() -> anyhow::Result<()> {
+    let date = NaiveDate::from_ymd_opt(2026, 7, 1).unwrap();
+    assert_eq!(end_of_day(date)?.to_rfc3339(), "2026-07-01T21:59:59+00:00");
+    Ok(())
+}
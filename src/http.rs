@@ -1,7 +1,12 @@
-use ureq::RequestBuilder;
-use ureq::http::HeaderValue;
-use ureq::http::header::{FROM, USER_AGENT};
-use ureq::typestate::WithoutBody;
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ureq::http::header::{AUTHORIZATION, CONTENT_TYPE, FROM, USER_AGENT};
+use ureq::http::{HeaderValue, Request, Response};
+use ureq::typestate::{WithBody, WithoutBody};
+use ureq::{Agent, Body, RequestBuilder, ResponseExt as _};
+use url::Url;
 
 const FROM_VALUE: &str = "calendarbot-downloader@hawhh.de";
 const USER_AGENT_VALUE: &str = concat!(
@@ -12,8 +17,38 @@ const USER_AGENT_VALUE: &str = concat!(
     env!("CARGO_PKG_REPOSITORY"),
 );
 
+/// Wait between requests to not stress the servers
+#[cfg(not(debug_assertions))]
+const POLITENESS_DELAY: Duration = Duration::from_millis(200);
+#[cfg(debug_assertions)]
+const POLITENESS_DELAY: Duration = Duration::ZERO;
+
+/// Shared agent so cookies of a login are used by all following requests
+static AGENT: LazyLock<Agent> = LazyLock::new(Agent::new_with_defaults);
+static LAST_REQUEST: Mutex<Option<Instant>> = Mutex::new(None);
+
+fn wait_politely() {
+    let mut last_request = LAST_REQUEST.lock().unwrap();
+    if let Some(remaining) =
+        last_request.and_then(|last| POLITENESS_DELAY.checked_sub(last.elapsed()))
+    {
+        thread::sleep(remaining);
+    }
+    *last_request = Some(Instant::now());
+}
+
 fn get_with_headers(url: &str) -> RequestBuilder<WithoutBody> {
-    ureq::get(url)
+    wait_politely();
+    AGENT
+        .get(url)
+        .header(FROM, HeaderValue::from_static(FROM_VALUE))
+        .header(USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE))
+}
+
+fn post_with_headers(url: &str) -> RequestBuilder<WithBody> {
+    wait_politely();
+    AGENT
+        .post(url)
         .header(FROM, HeaderValue::from_static(FROM_VALUE))
         .header(USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE))
 }
@@ -22,12 +57,54 @@ pub fn get_text(url: &str) -> Result<String, ureq::Error> {
     get_with_headers(url).call()?.into_body().read_to_string()
 }
 
-pub fn get_haw_text(url: &str) -> Result<String, ureq::Error> {
-    let bytes = get_with_headers(url).call()?.into_body().read_to_vec()?;
+/// Get a page and the url it ended up at after following redirects
+pub fn get_page(url: &str) -> anyhow::Result<(Url, String)> {
+    into_page(get_with_headers(url).call()?)
+}
+
+/// Submit a form and return the resulting page and the url it ended up at after following redirects
+pub fn post_form(url: &str, fields: &[(String, String)]) -> anyhow::Result<(Url, String)> {
+    into_page(post_with_headers(url).send_form(fields.iter().map(|(name, value)| (name, value)))?)
+}
 
-    // Also known as ISO-8859-1 but that doesnt seem to be a defined standard.
-    // https://docs.rs/encoding_rs/0.8.35/encoding_rs/index.html#iso-8859-1
-    let haw_text = encoding_rs::mem::decode_latin1(&bytes).into_owned();
+fn into_page(response: Response<Body>) -> anyhow::Result<(Url, String)> {
+    let url = Url::parse(&response.get_uri().to_string())?;
+    let body = response.into_body().read_to_string()?;
+    Ok((url, body))
+}
+
+/// Send a `WebDAV` request like `PROPFIND` or `REPORT`.
+///
+/// Returns the status code as `WebDAV` reports errors like an invalid sync token with it.
+pub fn dav(
+    method: &str,
+    url: &str,
+    depth: &str,
+    authorization: Option<&str>,
+    body: String,
+) -> anyhow::Result<(u16, String)> {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(url)
+        .header(FROM, FROM_VALUE)
+        .header(USER_AGENT, USER_AGENT_VALUE)
+        .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+        .header("Depth", depth);
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    wait_politely();
+    let request = AGENT
+        .configure_request(request.body(body)?)
+        .allow_non_standard_methods(true)
+        .http_status_as_error(false)
+        .build();
+    let response = AGENT.run(request)?;
+    let status = response.status().as_u16();
+    let body = response.into_body().read_to_string()?;
+    Ok((status, body))
+}
 
-    Ok(haw_text)
+pub fn get_bytes(url: &str) -> Result<Vec<u8>, ureq::Error> {
+    get_with_headers(url).call()?.into_body().read_to_vec()
 }
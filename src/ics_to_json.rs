@@ -15,7 +15,6 @@ pub fn parse(ics_body: &str) -> anyhow::Result<Vec<EventEntry>> {
 
     for cap in event_regex.captures_iter(&sane_body) {
         let dozent = cap[3].trim();
-        // let uid = &cap[4];
 
         result.push(EventEntry {
             name: cap[1].trim().to_owned(),
@@ -23,6 +22,7 @@ pub fn parse(ics_body: &str) -> anyhow::Result<Vec<EventEntry>> {
             description: parse_description(dozent),
             start: parse_datetime(cap[5].trim())?,
             end: parse_datetime(cap[6].trim())?,
+            uid: cap[4].trim().to_owned(),
         });
     }
 
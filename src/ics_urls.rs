@@ -1,84 +1,242 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::Path;
 
-use anyhow::Context as _;
-use lazy_regex::regex;
+use lazy_regex::regex_replace;
+use percent_encoding::percent_decode_str;
+use scraper::{Html, Selector};
 use url::Url;
 
+use crate::caldav::{self, CalDav};
+use crate::config::{Crawl, Kind, Source};
 use crate::http::get_text;
+use crate::local;
+use crate::myhaw::MyHaw;
 
-pub fn get_all() -> HashMap<&'static str, Vec<Url>> {
-    let sources = [
-        (
-            "informatik",
-            "https://userdoc.informatik.haw-hamburg.de/doku.php?id=stundenplan:ics_public",
-        ),
-        (
-            "information-engineering",
-            "https://www.haw-hamburg.de/en/bachelor-information-engineering-students/",
-        ),
-        (
-            "elektrotechnik-und-informationstechnik",
-            "https://www.haw-hamburg.de/bachelor-elektrotechnik-und-informationstechnik-studierende/",
-        ),
-        (
-            "informations-und-elektrotechnik",
-            "https://www.haw-hamburg.de/hochschule/technik-und-informatik/departments/informations-und-elektrotechnik/studium/studienorganisation/studienplaene/",
-        ),
-    ];
-
-    let mut result = HashMap::new();
-    for (base, url) in sources {
-        let mut below = Vec::new();
-        let url = Url::parse(url).unwrap();
-        match get_from_url(&url) {
-            Ok(mut urls) => below.append(&mut urls),
-            Err(err) => println!("WARNING: skip base url {url} {err:#}"),
+/// Discovered link to an ICS file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Link {
+    pub url: Url,
+    /// Text of the HTML link or the file name
+    pub text: String,
+}
+
+impl Link {
+    pub fn from_file_name(url: Url) -> Self {
+        let text = url
+            .path_segments()
+            .and_then(Iterator::last)
+            .map(|name| percent_decode_str(name).decode_utf8_lossy().into_owned())
+            .unwrap_or_default();
+        Self { url, text }
+    }
+}
+
+/// Discover the ICS links of all enabled sources
+pub fn get_all(sources: &[Source]) -> Vec<(&Source, anyhow::Result<Vec<Link>>)> {
+    sources
+        .iter()
+        .filter(|source| source.enabled)
+        .map(|source| {
+            let links = get_from_source(source).map(|mut links| {
+                if let Some(link_pattern) = &source.link_pattern {
+                    links.retain(|link| link_pattern.is_match(&link.url));
+                }
+                links.sort();
+                links.dedup_by(|later, earlier| later.url == earlier.url);
+                links
+            });
+            (source, links)
+        })
+        .collect()
+}
+
+fn get_from_source(source: &Source) -> anyhow::Result<Vec<Link>> {
+    match source.kind {
+        Kind::Index => {
+            let mut result = Vec::new();
+            for url in &source.urls {
+                match get_from_url(url, source.crawl.as_ref()) {
+                    Ok(mut urls) => result.append(&mut urls),
+                    Err(err) => println!("WARNING: skip base url {url} {err:#}"),
+                }
+            }
+            Ok(result)
+        }
+        Kind::MyHaw => MyHaw::new(source.login_url.as_ref(), &source.urls)?.get_ics_urls(),
+        Kind::Local => {
+            let mut result = Vec::new();
+            for url in &source.urls {
+                result.extend(
+                    local::get_ics_urls(url)?
+                        .into_iter()
+                        .map(Link::from_file_name),
+                );
+            }
+            Ok(result)
+        }
+        Kind::CalDav => {
+            let mirror = Path::new(caldav::FOLDER).join(&source.base);
+            let urls = CalDav::new(source.urls[0].clone(), mirror)?.get_ics_urls()?;
+            Ok(urls.into_iter().map(Link::from_file_name).collect())
         }
-        below.sort();
-        below.dedup();
-        result.insert(base, below);
     }
-    result
 }
 
-fn get_from_url(base_url: &Url) -> anyhow::Result<Vec<Url>> {
-    let body = get_text(base_url.as_str())?;
-    let urls = get_from_body(base_url, &body)?;
+/// Get the ICS links of the page and when configured of its subpages
+fn get_from_url(base_url: &Url, crawl: Option<&Crawl>) -> anyhow::Result<Vec<Link>> {
+    let max_depth = crawl.map_or(0, |crawl| crawl.depth);
+    let mut visited = HashSet::from([without_fragment(base_url)]);
+    let mut pages = vec![base_url.clone()];
+    let mut urls = Vec::new();
+    for depth in 0..=max_depth {
+        let mut subpages = Vec::new();
+        for page in pages {
+            let body = match get_text(page.as_str()) {
+                Ok(body) => body,
+                Err(err) if depth > 0 => {
+                    println!("WARNING: skip subpage {page} {err:#}");
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let links = get_links(&page, &body);
+            for link in links {
+                if is_ics_url(&link.url) {
+                    urls.push(link);
+                } else if depth < max_depth
+                    && link.url.host_str() == base_url.host_str()
+                    && crawl
+                        .and_then(|crawl| crawl.page_pattern.as_ref())
+                        .is_none_or(|pattern| pattern.is_match(&link.url))
+                    && visited.insert(without_fragment(&link.url))
+                {
+                    subpages.push(link.url);
+                }
+            }
+        }
+        pages = subpages;
+    }
     anyhow::ensure!(!urls.is_empty(), "no ics urls found");
     Ok(urls)
 }
 
-fn get_from_body(base_url: &Url, body: &str) -> Result<Vec<Url>, url::ParseError> {
-    let mut result = Vec::new();
-    for cap in regex!(r#"href="(\S+\.ics)""#).captures_iter(body) {
-        let full_url = base_url.join(&cap[1])?;
-        result.push(full_url);
-    }
-    Ok(result)
+fn without_fragment(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url
 }
 
-/// As HAW is complicated, try to get the filestem of the file to be downloaded
-pub fn file_stem(url: &Url) -> anyhow::Result<&str> {
-    if url.path() == "/lib/exe/fetch.php" {
-        return informatik_userdoc_fetch_file_stem(url).context("informatik userdoc fetch");
+/// Find the links to ICS files in the HTML page.
+///
+/// Respects `<base href>`, ignores links within comments and normalises `webcal://` to `https://`.
+pub fn get_from_body(page_url: &Url, body: &str) -> Vec<Link> {
+    get_links(page_url, body)
+        .into_iter()
+        .filter(|link| is_ics_url(&link.url))
+        .collect()
+}
+
+/// All http links of the HTML page
+fn get_links(page_url: &Url, body: &str) -> Vec<Link> {
+    let html = Html::parse_document(body);
+    let base_selector = Selector::parse("base[href]").unwrap();
+    let link_selector = Selector::parse("a[href], link[href]").unwrap();
+
+    let base_url = html
+        .select(&base_selector)
+        .next()
+        .and_then(|base| base.value().attr("href"))
+        .and_then(|href| page_url.join(href).ok())
+        .unwrap_or_else(|| page_url.clone());
+
+    html.select(&link_selector)
+        .filter_map(|link| {
+            let href = link.value().attr("href")?;
+            let url = base_url.join(&normalize_webcal(href.trim())).ok()?;
+            let text = link.text().collect::<String>();
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            Some(Link { url, text })
+        })
+        .filter(|link| matches!(link.url.scheme(), "https" | "http"))
+        .collect()
+}
+
+fn normalize_webcal(href: &str) -> Cow<'_, str> {
+    regex_replace!(r"^webcals?://"i, href, "https://")
+}
+
+/// Either the path ends with `.ics` or a query like the `DokuWiki` `media` does
+fn is_ics_url(url: &Url) -> bool {
+    fn is_ics(value: &str) -> bool {
+        value.to_ascii_lowercase().ends_with(".ics")
     }
+    is_ics(url.path()) || url.query_pairs().any(|(_, value)| is_ics(&value))
+}
 
-    let filename = url
-        .path_segments()
-        .context("ICS url should always have a path")?
-        .next_back()
-        .context("path_segments should always return at least one segment")?;
-    let (filestem, _) = filename
-        .rsplit_once('.')
-        .context("should always contain a .")?;
-    Ok(filestem)
+#[test]
+fn html_links_are_found() {
+    let page = Url::parse("https://www.haw-hamburg.de/studium/plaene/").unwrap();
+    let body = r#"<html><head><base href="/files/"></head><body>
+        <a href="a.ics">double quoted</a>
+        <a href='b.ICS?version=2'>single quoted with query</a>
+        <a href="webcal://example.org/c.ics">webcal</a>
+        <a href="https://userdoc.informatik.haw-hamburg.de/lib/exe/fetch.php?tok=1&amp;media=stundenplan:d.ics">DokuWiki</a>
+        <!-- <a href="commented.ics">old</a> -->
+        <a href="e.pdf">not a calendar</a>
+        <a href="mailto:someone@example.org?subject=f.ics">not downloadable</a>
+    </body></html>"#;
+    let urls = get_from_body(&page, body)
+        .into_iter()
+        .map(|link| format!("{} {}", link.url, link.text))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        [
+            "https://www.haw-hamburg.de/files/a.ics double quoted",
+            "https://www.haw-hamburg.de/files/b.ICS?version=2 single quoted with query",
+            "https://example.org/c.ics webcal",
+            "https://userdoc.informatik.haw-hamburg.de/lib/exe/fetch.php?tok=1&media=stundenplan:d.ics DokuWiki",
+        ]
+    );
 }
 
-fn informatik_userdoc_fetch_file_stem(url: &Url) -> anyhow::Result<&str> {
-    let query = url.query().context("should have a query")?;
-    let capture = regex!(r"media=stundenplan:(.+)\.ics")
-        .captures(query)
-        .context("should match expected media query")?;
-    let filestem = capture.get(1).unwrap().as_str();
-    Ok(filestem)
+#[test]
+fn crawl_follows_matching_subpages_of_the_same_domain() -> anyhow::Result<()> {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::test_server::Response;
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    let server = crate::test_server::start(move |request| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        match request.path.as_str() {
+            "/plaene/" => Response::ok(
+                r#"<a href="ws/">WS</a> <a href="ss/">SS</a> <a href="/impressum/">Impressum</a> <a href="https://example.org/plaene/">external</a> <a href="top.ics">top</a>"#,
+            ),
+            "/plaene/ws/" => Response::ok(
+                r#"<a href="../">back</a> <a href="../ss/#anchor">SS</a> <a href="a.ics">a</a> <a href="deeper/">deeper</a>"#,
+            ),
+            "/plaene/ss/" => Response::ok(r#"<a href="b.ics">b</a>"#),
+            _ => Response::status(404),
+        }
+    });
+
+    let crawl: Crawl = toml::from_str(
+        r#"depth = 1
+page-pattern = "/plaene/""#,
+    )?;
+    let mut urls = get_from_url(&server.join("plaene/")?, Some(&crawl))?
+        .into_iter()
+        .map(|link| link.url.path().to_owned())
+        .collect::<Vec<_>>();
+    urls.sort();
+    assert_eq!(
+        urls,
+        ["/plaene/ss/b.ics", "/plaene/top.ics", "/plaene/ws/a.ics"]
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    Ok(())
 }
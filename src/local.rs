@@ -0,0 +1,66 @@
+//! ICS files from the local file system like mailed timetables or test fixtures
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use url::Url;
+
+fn to_path(url: &Url) -> anyhow::Result<PathBuf> {
+    url.to_file_path()
+        .ok()
+        .with_context(|| format!("not a local file url {url}"))
+}
+
+/// The file itself or all ICS files within the directory
+pub fn get_ics_urls(url: &Url) -> anyhow::Result<Vec<Url>> {
+    let path = to_path(url)?;
+    if !path.is_dir() {
+        anyhow::ensure!(path.is_file(), "{} does not exist", path.display());
+        return Ok(vec![url.clone()]);
+    }
+
+    let mut result = Vec::new();
+    for maybe_entry in fs::read_dir(&path)? {
+        let path = maybe_entry?.path();
+        if is_ics(&path) {
+            result.push(Url::from_file_path(&path).unwrap());
+        }
+    }
+    anyhow::ensure!(!result.is_empty(), "no ics files found");
+    Ok(result)
+}
+
+fn is_ics(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ics"))
+}
+
+pub fn read(url: &Url) -> anyhow::Result<Vec<u8>> {
+    Ok(fs::read(to_path(url)?)?)
+}
+
+#[test]
+fn directory_lists_ics_files() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("a.ics"), "BEGIN:VCALENDAR")?;
+    fs::write(dir.path().join("b.ICS"), b"LOCATION:Stiftstra\xdfe")?;
+    fs::write(dir.path().join("c.txt"), "not a calendar")?;
+    fs::create_dir(dir.path().join("d.ics"))?;
+
+    let mut urls = get_ics_urls(&Url::from_directory_path(dir.path()).unwrap())?;
+    urls.sort();
+    let names = urls
+        .iter()
+        .map(|url| url.path_segments().unwrap().next_back().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a.ics", "b.ICS"]);
+    assert_eq!(read(&urls[0])?, b"BEGIN:VCALENDAR");
+    assert_eq!(
+        crate::config::Encoding::Utf8OrLatin1.decode(&read(&urls[1])?),
+        "LOCATION:Stiftstraße"
+    );
+    Ok(())
+}
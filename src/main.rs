@@ -1,45 +1,357 @@
-use std::fs;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::{env, fs};
 
-use url::Url;
+use anyhow::Context as _;
+use chrono::{DateTime, NaiveDate, SubsecRound as _, Utc};
 
+use crate::config::Source;
 use crate::event_entry::EventEntry;
+use crate::events_git::{GitError, GitRepo};
+use crate::health::Health;
+use crate::ics_urls::Link;
+use crate::manifest::{Manifest, Outcome};
+use crate::summary::{BaseChanges, Summary};
 
+mod caldav;
+mod config;
+mod discovery;
+mod dry_run;
 mod event_entry;
+mod event_files;
 mod events_git;
+mod events_gix;
+mod file_stem;
 mod files;
+mod health;
+mod history;
 mod http;
 mod ics_to_json;
 mod ics_urls;
+mod local;
+mod manifest;
+mod metadata;
+mod myhaw;
+mod sanity;
+mod schema;
+mod summary;
+#[cfg(test)]
+mod test_server;
 mod v4;
+mod v5;
 
-fn main() {
-    download_ics();
+/// Within the events folder. Bases are downloaded into here and only swapped into place when complete.
+/// Being on the same file system as the events volume keeps the swap a rename.
+const STAGING_FOLDER: &str = ".staging";
+/// Exit code when the events repo could not be updated or published
+const EXIT_GIT: u8 = 2;
+/// How often a push rejected because of newer remote commits is attempted
+const PUSH_ATTEMPTS: usize = 3;
+
+fn main() -> ExitCode {
+    match env::args().nth(1).as_deref() {
+        None => run(),
+        Some("health") => print_health(),
+        Some("dry-run") => dry_run(),
+        Some("history") => print_history(&env::args().skip(2).collect::<Vec<_>>()),
+        Some("validate") => validate(env::args().nth(2).as_deref().unwrap_or(".")),
+        Some(other) => {
+            eprintln!(
+                "unknown command {other:?}. Run without arguments to download or use health, dry-run, history or validate"
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> ExitCode {
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("ERROR: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let repo = events_git::open(&config.git, events_git::FOLDER);
+    let result = update_events(&*repo, config.git.push, &config.sources);
+    if let Err(err) = &result
+        && !repo.exists()
+    {
+        println!("\nERROR: events repo {err}");
+        return ExitCode::from(EXIT_GIT);
+    }
 
     println!("\n\n## Generate merged eventfiles compatible with downloader v4");
     v4::update();
+    println!("\n\n## Generate v5 eventfiles");
+    v5::update(Utc::now().trunc_subsecs(0));
+    schema::save_eventfiles();
+
+    match result {
+        Ok(failed) if failed.is_empty() => ExitCode::SUCCESS,
+        Ok(failed) => {
+            println!("\nFlagged bases which were kept untouched: {failed:?}");
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            println!("\nERROR: events repo {err}");
+            ExitCode::from(EXIT_GIT)
+        }
+    }
+}
+
+/// Run the pipeline on a copy and print what would change
+fn dry_run() -> ExitCode {
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("ERROR: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match dry_run::run(&config) {
+        Ok(failed) if failed.is_empty() => ExitCode::SUCCESS,
+        Ok(failed) => {
+            println!("\nFlagged bases which would be kept untouched: {failed:?}");
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            println!("\nERROR: dry run {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Print the events of a base, file or event name as of a past date as JSON.
+///
+/// Usage: `history <YYYY-MM-DD> <base>[/<file>]` or `history <YYYY-MM-DD> --name <event name>`
+fn print_history(args: &[String]) -> ExitCode {
+    let result = args
+        .split_first()
+        .context("expected a date like 2026-04-01")
+        .and_then(|(date, selection)| {
+            let date = date.parse::<NaiveDate>().context("parse date")?;
+            let selection = history::Selection::parse(selection)?;
+            let config = config::load()?;
+            let repo = events_git::open(&config.git, events_git::FOLDER);
+            anyhow::ensure!(repo.exists(), "there is no events checkout yet");
+            history::events_at(&*repo, date, &selection)
+        });
+    match result {
+        Ok(events) => {
+            print!("{}", String::from_utf8_lossy(&files::to_json(&events)));
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("ERROR: history {err:#}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn download_ics() {
-    events_git::pull();
+/// List the degraded sources and fail when there are any
+fn print_health() -> ExitCode {
+    let degraded = Health::load().degraded();
+    if degraded.is_empty() {
+        println!("All sources are healthy");
+        return ExitCode::SUCCESS;
+    }
+    println!("Degraded sources:");
+    for line in degraded {
+        println!("{line}");
+    }
+    ExitCode::FAILURE
+}
 
-    let base_urls = ics_urls::get_all();
-    let url_amount = base_urls.values().flatten().count();
+/// Check the events and eventfiles folders within the root against the schemas
+fn validate(root: &str) -> ExitCode {
+    match schema::validate(Path::new(root)) {
+        Ok(problems) if problems.is_empty() => ExitCode::SUCCESS,
+        Ok(problems) => {
+            for (file, errors) in &problems {
+                println!("{file}:");
+                for error in errors {
+                    println!("  {error}");
+                }
+            }
+            println!("{} files do not match their schema", problems.len());
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            eprintln!("ERROR: validate {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Discover the links of all sources. Sources failing the discovery or its sanity checks are flagged.
+fn discover<'source>(
+    sources: &'source [Source],
+    previous_discovered: &discovery::Discovered,
+    health: &mut Health,
+    summary: &mut Summary,
+) -> Vec<(&'source Source, Vec<Link>)> {
+    ics_urls::get_all(sources)
+        .into_iter()
+        .filter_map(|(source, links)| {
+            let checked = links.and_then(|links| {
+                let (previous, vanished) =
+                    discovery::vanished(previous_discovered, &source.base, &links);
+                source
+                    .sanity
+                    .check_discovery(links.len(), previous, vanished)?;
+                Ok(links)
+            });
+            match checked {
+                Ok(links) => Some((source, links)),
+                Err(err) => {
+                    println!("WARNING: keep base {} untouched: {err:#}", source.base);
+                    health.source_failed(&source.base, &err);
+                    summary
+                        .failed
+                        .insert(source.base.clone(), format!("{err:#}"));
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Pull the events repo, download all sources into it and publish the changes.
+///
+/// Returns the bases which failed their sanity checks.
+fn update_events(
+    repo: &dyn GitRepo,
+    push: bool,
+    sources: &[Source],
+) -> Result<Vec<String>, GitError> {
+    if repo.exists() {
+        repo.pull()?;
+    } else {
+        repo.clone_remote()?;
+    }
+
+    let (summary, staged) = download_ics(repo, sources);
+    for pathspec in &staged {
+        repo.add(pathspec)?;
+    }
+
+    if !repo.has_staged_changes()? {
+        println!("Nothing changed, skip commit and push");
+        return Ok(summary.failed.into_keys().collect());
+    }
+    let message = summary.commit_message();
+    repo.commit(&message)?;
+    if push {
+        push_with_retries(repo, &staged, &message)?;
+    } else {
+        println!("Skip push as configured");
+    }
+    Ok(summary.failed.into_keys().collect())
+}
+
+/// Push the commit. When the remote got newer commits meanwhile the commit is rebuilt on top of them.
+///
+/// The working tree still contains the generated files so the staged paths only need to be staged again.
+fn push_with_retries(repo: &dyn GitRepo, staged: &[String], message: &str) -> Result<(), GitError> {
+    let mut attempt = 1;
+    loop {
+        match repo.push() {
+            Err(GitError::Rejected(reason)) if attempt < PUSH_ATTEMPTS => {
+                println!("WARNING: push attempt {attempt} rejected, rebuild the commit: {reason}");
+                repo.reset_to_upstream()?;
+                for pathspec in staged {
+                    repo.add(pathspec)?;
+                }
+                let has_changes = repo.has_staged_changes()?;
+                if has_changes {
+                    repo.commit(message)?;
+                }
+                repo.discard_unstaged()?;
+                if !has_changes {
+                    println!("The remote already contains the changes");
+                    return Ok(());
+                }
+                attempt += 1;
+            }
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                // the unpublished commit would diverge from upstream and block the pull of the next run
+                if let Err(reset_err) = repo
+                    .reset_to_upstream()
+                    .and_then(|()| repo.discard_unstaged())
+                {
+                    println!("WARNING: reset to upstream after the failed push: {reset_err}");
+                }
+                return Err(match err {
+                    GitError::Rejected(reason) => GitError::Rejected(format!(
+                        "{reason}\ngave up after {PUSH_ATTEMPTS} attempts"
+                    )),
+                    other => other,
+                });
+            }
+        }
+    }
+}
+
+/// Download all sources into the events folder and summarise the changes and failures.
+///
+/// Also returns the paths within the events folder which have to be staged.
+fn download_ics(repo: &dyn GitRepo, sources: &[Source]) -> (Summary, Vec<String>) {
+    prepare_staging();
+    let previous_discovered = discovery::load();
+    let mut health = Health::load();
+    let mut summary = Summary::default();
+    let base_urls = discover(sources, &previous_discovered, &mut health, &mut summary);
+    let url_amount = base_urls.iter().map(|(_, urls)| urls.len()).sum::<usize>();
     println!("ICS total urls: {url_amount}");
 
+    let mut staged = Vec::new();
+
+    let previous = Manifest::load();
+    let mut manifest = Manifest::default();
+    let now = Utc::now().trunc_subsecs(0);
+
     #[cfg(debug_assertions)]
     let mut current: usize = 0;
-    let mut successful: usize = 0;
-
-    #[expect(clippy::iter_over_hash_type)]
-    for (base, urls) in base_urls {
-        let path = Path::new(events_git::FOLDER).join(base);
-        drop(fs::remove_dir_all(&path)); // Allowed to be empty
-        fs::create_dir_all(&path).expect("create dir for base should work");
-        for url in urls {
-            match one_url(&path, &url) {
-                Ok(()) => successful += 1,
-                Err(err) => println!("WARNING: skip ics file url {url} {err:#}"),
+    let mut new: usize = 0;
+    let mut changed: usize = 0;
+    let mut unchanged: usize = 0;
+
+    for (source, links) in &base_urls {
+        let path = Path::new(events_git::FOLDER).join(&source.base);
+        let staging = fresh_staging_dir(&source.base);
+        let mut downloaded: usize = 0;
+        let mut written = HashSet::new();
+        let planned_file_stems = file_stem::for_links(&*source.file_stem, links);
+        for (link, planned_file_stem) in links.iter().zip(planned_file_stems) {
+            let result = planned_file_stem.and_then(|planned_file_stem| {
+                one_url(
+                    &staging,
+                    &path,
+                    source,
+                    link,
+                    planned_file_stem,
+                    &mut written,
+                    &previous,
+                    &mut manifest,
+                )
+            });
+            match result {
+                Ok(outcome) => {
+                    health.url_succeeded(&source.base, &link.url);
+                    downloaded += 1;
+                    match outcome {
+                        Outcome::New => new += 1,
+                        Outcome::Changed => changed += 1,
+                        Outcome::Unchanged => unchanged += 1,
+                    }
+                }
+                Err(err) => {
+                    println!("WARNING: skip ics file url {} {err:#}", link.url);
+                    health.url_failed(&source.base, &link.url, &err);
+                }
             }
 
             #[cfg(debug_assertions)]
@@ -49,25 +361,304 @@ fn download_ics() {
                     println!("ICS file download {current:4}/{url_amount}");
                 }
             }
+        }
+        if let Err(err) = source.sanity.check_downloads(downloaded, links.len()) {
+            println!("WARNING: keep base {} untouched: {err:#}", source.base);
+            health.source_failed(&source.base, &err);
+            fs::remove_dir_all(&staging).expect("remove staging dir should work");
+            for link in links {
+                manifest.restore(&previous, link.url.as_str());
+            }
+            summary
+                .failed
+                .insert(source.base.clone(), format!("{err:#}"));
+            continue;
+        }
+        swap_in_base(
+            repo,
+            &source.base,
+            &staging,
+            &path,
+            now,
+            &mut summary,
+            &mut staged,
+        );
+        let urls = links.iter().map(|link| &link.url).collect::<Vec<_>>();
+        health.source_succeeded(&source.base, &urls);
+    }
+
+    let successful = new + changed + unchanged;
+    println!(
+        "ICS downloaded {successful} urls: {new} new, {changed} changed, {unchanged} unchanged"
+    );
+    // only published urls are compared against in the next run
+    let untouched = untouched_bases(sources, &summary);
+    discovery::update(&previous_discovered, &base_urls, &untouched);
+    manifest.restore_bases(&previous, &previous_discovered, &untouched);
+    staged.push(discovery::FILE.to_owned());
+    staged.push(discovery::REPORT_FILE.to_owned());
+    manifest.save();
+    staged.push(manifest::FILE.to_owned());
+    health.retain_sources(sources);
+    health.save();
+    staged.push(health::FILE.to_owned());
+    staged.append(&mut schema::save_events());
+    (summary, staged)
+}
+
+/// Bases which keep their contents of the last run: flagged and disabled ones
+fn untouched_bases(sources: &[Source], summary: &Summary) -> Vec<String> {
+    let disabled = sources
+        .iter()
+        .filter(|source| !source.enabled)
+        .map(|source| source.base.clone());
+    summary.failed.keys().cloned().chain(disabled).collect()
+}
+
+fn staging_root() -> PathBuf {
+    Path::new(events_git::FOLDER).join(STAGING_FOLDER)
+}
+
+/// Keep the staging folder out of git and restore everything an interrupted run left swapped out
+fn prepare_staging() {
+    events_git::exclude(Path::new(events_git::FOLDER), STAGING_FOLDER)
+        .expect("excluding the staging folder from git should work");
+    files::restore_swapped_out(&staging_root(), Path::new(events_git::FOLDER))
+        .expect("restoring interrupted swaps should work");
+}
+
+fn fresh_staging_dir(base: &str) -> PathBuf {
+    let staging = staging_root().join(base);
+    if staging.exists() {
+        // leftover of an aborted run
+        fs::remove_dir_all(&staging).expect("remove old staging dir should work");
+    }
+    fs::create_dir_all(&staging).expect("create staging dir for base should work");
+    staging
+}
+
+/// Summarise the changes and update the metadata of the completely staged base before replacing the committed one
+fn swap_in_base(
+    repo: &dyn GitRepo,
+    base: &str,
+    staging: &Path,
+    committed: &Path,
+    now: DateTime<Utc>,
+    summary: &mut Summary,
+    staged: &mut Vec<String>,
+) {
+    match BaseChanges::compare(committed, staging) {
+        Ok(base_changes) => summary.insert(base, base_changes),
+        Err(err) => println!("WARNING: summarise changes of {base}: {err:#}"),
+    }
+    match metadata::update_base(repo, base, committed, staging, &staging_root(), now) {
+        Ok(()) => staged.push(format!("{}/{base}", metadata::FOLDER)),
+        Err(err) => println!("WARNING: update metadata of {base}: {err:#}"),
+    }
+    files::swap_dir(staging, committed).expect("swapping in the staged base should work");
+    staged.push(base.to_owned());
+}
+
+/// Write the events of the url into the staging dir.
+///
+/// Unchanged files are copied from the committed base instead of parsing them again.
+/// File stems depending on the content are disambiguated against the stems already written for the base.
+#[expect(clippy::too_many_arguments)]
+fn one_url(
+    staging: &Path,
+    committed: &Path,
+    source: &Source,
+    link: &Link,
+    planned_file_stem: Option<String>,
+    written: &mut HashSet<String>,
+    previous: &Manifest,
+    manifest: &mut Manifest,
+) -> anyhow::Result<Outcome> {
+    let url = &link.url;
+    let bytes = if url.scheme() == "file" {
+        local::read(url)?
+    } else {
+        http::get_bytes(url.as_str())?
+    };
+    let ics_body = source.encoding().decode(&bytes);
+    let hash = manifest::content_hash(&ics_body);
+
+    let mut filestem = match planned_file_stem {
+        Some(filestem) => filestem,
+        None => files::sanitize_name(&source.file_stem.by_content(link, &ics_body)?)?,
+    };
+    if !written.insert(filestem.clone()) {
+        file_stem::disambiguate(&mut filestem, link);
+        written.insert(filestem.clone());
+    }
+    let file_name = format!("{filestem}.json");
+    let staged = staging.join(&file_name);
+    let committed = committed.join(&file_name);
+
+    let outcome = match previous.outcome(url, &hash, &file_name) {
+        Outcome::Unchanged if !committed.exists() => Outcome::Changed,
+        outcome => outcome,
+    };
+    if outcome == Outcome::Unchanged {
+        fs::copy(&committed, &staged)?;
+    } else {
+        let events = ics_to_json::parse(&ics_body)?;
+        files::save_to_json(&staged, &events);
+    }
+
+    manifest.insert(url, hash, file_name);
+    Ok(outcome)
+}
+
+#[test]
+fn failed_push_leaves_the_branch_at_upstream() {
+    use std::cell::RefCell;
 
-            // Wait between requests
-            #[cfg(not(debug_assertions))]
-            std::thread::sleep(std::time::Duration::from_millis(200));
+    #[derive(Debug, Default)]
+    struct FailingPush {
+        rejected: bool,
+        calls: RefCell<Vec<&'static str>>,
+    }
+    impl FailingPush {
+        fn call(&self, name: &'static str) {
+            self.calls.borrow_mut().push(name);
         }
-        events_git::add(base);
     }
+    impl GitRepo for FailingPush {
+        fn exists(&self) -> bool {
+            true
+        }
+        fn clone_remote(&self) -> Result<(), GitError> {
+            self.call("clone");
+            Ok(())
+        }
+        fn pull(&self) -> Result<(), GitError> {
+            self.call("pull");
+            Ok(())
+        }
+        fn add(&self, _: &str) -> Result<(), GitError> {
+            self.call("add");
+            Ok(())
+        }
+        fn has_staged_changes(&self) -> Result<bool, GitError> {
+            Ok(true)
+        }
+        fn commit(&self, _: &str) -> Result<(), GitError> {
+            self.call("commit");
+            Ok(())
+        }
+        fn push(&self) -> Result<(), GitError> {
+            self.call("push");
+            Err(if self.rejected {
+                GitError::Rejected("fetch first".to_owned())
+            } else {
+                GitError::Gix {
+                    operation: "push",
+                    source: anyhow::anyhow!("network is unreachable"),
+                }
+            })
+        }
+        fn reset_to_upstream(&self) -> Result<(), GitError> {
+            self.call("reset");
+            Ok(())
+        }
+        fn discard_unstaged(&self) -> Result<(), GitError> {
+            self.call("discard");
+            Ok(())
+        }
+        fn commit_before(&self, _: DateTime<Utc>) -> Result<Option<String>, GitError> {
+            Ok(None)
+        }
+        fn list_dir(&self, _: &str, _: &str) -> Result<Vec<events_git::DirEntry>, GitError> {
+            Ok(Vec::new())
+        }
+        fn read_file(&self, _: &str, _: &str) -> Result<String, GitError> {
+            Ok(String::new())
+        }
+    }
+
+    let repo = FailingPush {
+        rejected: true,
+        ..FailingPush::default()
+    };
+    let err = push_with_retries(&repo, &["informatik".to_owned()], "update").unwrap_err();
+    assert!(matches!(err, GitError::Rejected(_)), "{err}");
+    let calls = repo.calls.into_inner();
+    assert_eq!(
+        calls.iter().filter(|&&call| call == "push").count(),
+        PUSH_ATTEMPTS
+    );
+    assert_eq!(calls[calls.len() - 3..], ["push", "reset", "discard"]);
 
-    println!("ICS downloaded {successful} urls");
-    events_git::commit_and_push();
+    let repo = FailingPush::default();
+    let err = push_with_retries(&repo, &["informatik".to_owned()], "update").unwrap_err();
+    assert!(matches!(err, GitError::Gix { .. }), "{err}");
+    assert_eq!(repo.calls.into_inner(), ["push", "reset", "discard"]);
 }
 
-fn one_url(path: &Path, url: &Url) -> anyhow::Result<()> {
-    let filestem = ics_urls::file_stem(url)?;
-    let path = path.join(format!("{filestem}.json"));
+#[test]
+fn identical_contents_get_distinct_file_stems() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let staging = dir.path().join("staging");
+    fs::create_dir(&staging)?;
+    let source: Source = toml::from_str(
+        r#"
+base = "mailed"
+kind = "local"
+urls = []
+file-stem = "content-hash"
+"#,
+    )?;
+    let mut written = HashSet::new();
+    let mut manifest = Manifest::default();
+    for name in ["a.ics", "b.ics"] {
+        let path = dir.path().join(name);
+        fs::write(&path, "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nEND:VCALENDAR\r\n")?;
+        let link = Link {
+            url: url::Url::from_file_path(path).expect("path is absolute"),
+            text: name.to_owned(),
+        };
+        one_url(
+            &staging,
+            &dir.path().join("committed"),
+            &source,
+            &link,
+            None,
+            &mut written,
+            &Manifest::default(),
+            &mut manifest,
+        )?;
+    }
+    assert_eq!(files::list_files(&staging)?.len(), 2);
+    Ok(())
+}
+
+/// The events folder is a volume in the container, the swap has to stay within it
+#[cfg(unix)]
+#[test]
+fn swap_within_a_mounted_events_folder() -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt as _;
 
-    let ics_body = http::get_haw_text(url.as_str())?;
-    let events = ics_to_json::parse(&ics_body)?;
+    let shm = Path::new("/dev/shm");
+    if !shm.is_dir() {
+        return Ok(());
+    }
+    let events = tempfile::tempdir_in(shm)?;
+    let committed = events.path().join("informatik");
+    let staging = events.path().join(STAGING_FOLDER).join("informatik");
+    fs::create_dir_all(&committed)?;
+    fs::create_dir_all(&staging)?;
+    fs::write(staging.join("a.json"), "[]")?;
+
+    files::swap_dir(&staging, &committed)?;
+    assert!(committed.join("a.json").exists());
+    assert!(!staging.exists());
 
-    files::save_to_json(&path, &events);
+    let outside = tempfile::tempdir()?;
+    if fs::metadata(outside.path())?.dev() != fs::metadata(events.path())?.dev() {
+        // a staging folder outside of the volume can not be renamed into it
+        let err = files::swap_dir(outside.path(), &committed).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::CrossesDevices);
+    }
     Ok(())
 }
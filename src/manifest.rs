@@ -0,0 +1,151 @@
+//! Remembers the content hash and eventfile of every downloaded ICS file to skip parsing unchanged files
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use url::Url;
+
+use crate::discovery::Discovered;
+use crate::{events_git, files};
+
+pub const FILE: &str = "manifest.json";
+/// Part of every content hash. Increase it when the same ICS content results in different eventfiles
+/// so every file is parsed again instead of keeping the output of the older parser.
+const PARSER_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    New,
+    Changed,
+    Unchanged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    hash: String,
+    /// Name of the eventfile within the base which was written for the url
+    file: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Manifest {
+    /// Load the manifest of the last run. Missing, broken or outdated manifests are treated as empty.
+    pub fn load() -> Self {
+        let path = Path::new(events_git::FOLDER).join(FILE);
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_str(&content) {
+            Ok(entries) => Self { entries },
+            Err(err) => {
+                println!("WARNING: ignore broken {}: {err}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        files::save_to_json(Path::new(events_git::FOLDER).join(FILE), &self.entries);
+    }
+
+    /// Compare the content of the url with the last run.
+    /// It is only unchanged when the same file was written for the url as the file might stem from another url otherwise.
+    pub fn outcome(&self, url: &Url, hash: &str, file: &str) -> Outcome {
+        match self.entries.get(url.as_str()) {
+            None => Outcome::New,
+            Some(entry) if entry.hash == hash && entry.file == file => Outcome::Unchanged,
+            Some(_) => Outcome::Changed,
+        }
+    }
+
+    pub fn insert(&mut self, url: &Url, hash: String, file: String) {
+        self.entries.insert(url.to_string(), Entry { hash, file });
+    }
+
+    /// Keep the entry of the previous manifest as the file of the url was not updated
+    pub fn restore(&mut self, previous: &Self, url: &str) {
+        match previous.entries.get(url) {
+            Some(entry) => self.entries.insert(url.to_owned(), entry.clone()),
+            None => self.entries.remove(url),
+        };
+    }
+
+    /// Keep the entries of the urls the bases had in the last run as the bases were not downloaded
+    pub fn restore_bases(&mut self, previous: &Self, discovered: &Discovered, bases: &[String]) {
+        for urls in bases.iter().filter_map(|base| discovered.get(base)) {
+            for url in urls {
+                self.restore(previous, url);
+            }
+        }
+    }
+}
+
+/// Hash of the ICS content parsed by the current parser
+pub fn content_hash(ics_body: &str) -> String {
+    hash(&format!("parser v{PARSER_VERSION}\n{ics_body}"))
+}
+
+pub fn hash(content: &str) -> String {
+    Sha256::digest(content)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+#[test]
+fn hash_is_hex_sha256() {
+    assert_eq!(
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        hash("")
+    );
+}
+
+#[test]
+fn untouched_bases_keep_their_entries() {
+    let url = |path: &str| Url::parse(&format!("https://example.org/{path}")).unwrap();
+    let mut previous = Manifest::default();
+    previous.insert(&url("a.ics"), "a".to_owned(), "a.json".to_owned());
+    previous.insert(&url("b.ics"), "b".to_owned(), "b.json".to_owned());
+    let discovered = Discovered::from([
+        ("flagged".to_owned(), [url("a.ics").to_string()].into()),
+        ("downloaded".to_owned(), [url("b.ics").to_string()].into()),
+    ]);
+
+    let mut manifest = Manifest::default();
+    manifest.restore_bases(&previous, &discovered, &["flagged".to_owned()]);
+    assert_eq!(
+        manifest.outcome(&url("a.ics"), "a", "a.json"),
+        Outcome::Unchanged
+    );
+    assert_eq!(manifest.outcome(&url("b.ics"), "b", "b.json"), Outcome::New);
+}
+
+#[test]
+fn unchanged_only_with_the_same_file() {
+    let url = Url::parse("https://example.org/a.ics").unwrap();
+    let mut manifest = Manifest::default();
+    manifest.insert(&url, "a".to_owned(), "BAI1-SE1.json".to_owned());
+    assert_eq!(
+        manifest.outcome(&url, "a", "BAI1-SE1.json"),
+        Outcome::Unchanged
+    );
+    assert_eq!(
+        manifest.outcome(&url, "b", "BAI1-SE1.json"),
+        Outcome::Changed
+    );
+    // the stem of the url changed, the committed file was written for another url
+    assert_eq!(
+        manifest.outcome(&url, "a", "BAI1-SE2.json"),
+        Outcome::Changed
+    );
+}
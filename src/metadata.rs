@@ -0,0 +1,336 @@
+//! When the events first appeared and when their time or room last changed.
+//!
+//! Every run compares the committed events with the new ones so the metadata follows the history of the events repo.
+//! Files without metadata yet get it from replaying their history first.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Europe::Berlin;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::events_git::GitRepo;
+use crate::{EventEntry, events_git, files, history};
+
+/// Within the events folder. Contains a file for every eventfile at the same path.
+pub const FOLDER: &str = "metadata";
+/// How many days of history are replayed for files without metadata
+const HISTORY_DAYS: usize = 180;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EventMetadata {
+    /// When the event first appeared
+    pub first_seen: DateTime<Utc>,
+    /// Last change of the start, end or location
+    pub last_changed: DateTime<Utc>,
+}
+
+/// Metadata by event key of an eventfile
+pub type FileMetadata = BTreeMap<String, EventMetadata>;
+
+/// Keys of the events. Repeated keys get the number of the occurrence appended.
+fn keys(events: &[EventEntry], key: fn(&EventEntry) -> String) -> Vec<String> {
+    let mut seen = BTreeMap::<String, usize>::new();
+    events
+        .iter()
+        .map(|event| {
+            let key = key(event);
+            let amount = seen.entry(key.clone()).or_default();
+            *amount += 1;
+            if *amount == 1 {
+                key
+            } else {
+                format!("{key}#{amount}")
+            }
+        })
+        .collect()
+}
+
+/// Metadata of the events based on the previous events of the same file and their metadata.
+///
+/// Events which got their UID since the previous run are found by the key they had without it.
+pub fn update(
+    previous_events: &[EventEntry],
+    previous: &FileMetadata,
+    events: &[EventEntry],
+    now: DateTime<Utc>,
+) -> FileMetadata {
+    let previous_events = keys(previous_events, EventEntry::key)
+        .into_iter()
+        .zip(previous_events)
+        .collect::<BTreeMap<_, _>>();
+    let fields_keys = keys(events, EventEntry::fields_key);
+    keys(events, EventEntry::key)
+        .into_iter()
+        .zip(fields_keys)
+        .zip(events)
+        .map(|((key, fields_key), event)| {
+            let previous_key = if previous_events.contains_key(&key) {
+                &key
+            } else {
+                &fields_key
+            };
+            let metadata = match (
+                previous_events.get(previous_key),
+                previous.get(previous_key),
+            ) {
+                (Some(before), Some(metadata)) => {
+                    let moved = before.start != event.start
+                        || before.end != event.end
+                        || before.location != event.location;
+                    EventMetadata {
+                        first_seen: metadata.first_seen,
+                        last_changed: if moved { now } else { metadata.last_changed },
+                    }
+                }
+                _ => EventMetadata {
+                    first_seen: now,
+                    last_changed: now,
+                },
+            };
+            (key, metadata)
+        })
+        .collect()
+}
+
+/// Metadata of the files of the base replayed from the last commit of every day in the history.
+///
+/// The times are only exact to the day. Shallow clones only provide the history they contain.
+fn from_history(
+    repo: &dyn GitRepo,
+    base: &str,
+    names: &BTreeSet<String>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<BTreeMap<String, FileMetadata>> {
+    // newest first
+    let mut states = Vec::<(DateTime<Utc>, BTreeMap<String, Vec<EventEntry>>)>::new();
+    let mut newer_commit = None;
+    let mut date = now.with_timezone(&Berlin).date_naive();
+    for _ in 0..HISTORY_DAYS {
+        let time = history::end_of_day(date)?.min(now);
+        let Some(commit) = repo.commit_before(time)? else {
+            break;
+        };
+        if newer_commit.as_ref() == Some(&commit) {
+            // no commit on that day, the state is older than assumed
+            if let Some((newer_time, _)) = states.last_mut() {
+                *newer_time = time;
+            }
+        } else {
+            // the base did not exist back then
+            let Ok(entries) = repo.list_dir(&commit, base) else {
+                break;
+            };
+            let mut state = BTreeMap::new();
+            for entry in entries {
+                if entry.is_dir || !names.contains(&entry.name) {
+                    continue;
+                }
+                let content = repo.read_file(&commit, &format!("{base}/{}", entry.name))?;
+                // broken files are skipped like they did not exist
+                if let Ok(events) = serde_json::from_str::<Vec<EventEntry>>(&content) {
+                    state.insert(entry.name, events);
+                }
+            }
+            if state.is_empty() {
+                break;
+            }
+            states.push((time, state));
+            newer_commit = Some(commit);
+        }
+        let Some(previous_date) = date.pred_opt() else {
+            break;
+        };
+        date = previous_date;
+    }
+
+    let mut replayed = BTreeMap::<String, (Vec<EventEntry>, FileMetadata)>::new();
+    for (time, state) in states.into_iter().rev() {
+        for (name, events) in state {
+            let (previous_events, previous) = replayed.remove(&name).unwrap_or_default();
+            let metadata = update(&previous_events, &previous, &events, time);
+            replayed.insert(name, (events, metadata));
+        }
+    }
+    Ok(replayed
+        .into_iter()
+        .map(|(name, (_, metadata))| (name, metadata))
+        .collect())
+}
+
+fn read_json<T: for<'de> Deserialize<'de> + Default>(path: &Path) -> anyhow::Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Stage the metadata of all files of the staged base and swap it in.
+///
+/// Has to happen before the staged base itself is swapped in as the committed events are compared.
+pub fn update_base(
+    repo: &dyn GitRepo,
+    base: &str,
+    committed: &Path,
+    staged: &Path,
+    staging_root: &Path,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let target = Path::new(events_git::FOLDER).join(FOLDER).join(base);
+    let staged_metadata = staging_root.join(FOLDER).join(base);
+    if staged_metadata.exists() {
+        fs::remove_dir_all(&staged_metadata)?;
+    }
+    fs::create_dir_all(&staged_metadata)?;
+    let names = files::json_files(staged)?;
+    let without_metadata = names
+        .iter()
+        .filter(|name| !target.join(name).exists())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let mut from_history = if without_metadata.is_empty() || !repo.exists() {
+        BTreeMap::new()
+    } else {
+        from_history(repo, base, &without_metadata, now).unwrap_or_else(|err| {
+            println!("WARNING: replay the history of {base}: {err:#}");
+            BTreeMap::new()
+        })
+    };
+    for name in names {
+        let events = read_json::<Vec<EventEntry>>(&staged.join(&name))?;
+        // broken previous files start over
+        let previous_events =
+            read_json::<Vec<EventEntry>>(&committed.join(&name)).unwrap_or_default();
+        let previous = from_history
+            .remove(&name)
+            .unwrap_or_else(|| read_json::<FileMetadata>(&target.join(&name)).unwrap_or_default());
+        let metadata = update(&previous_events, &previous, &events, now);
+        files::save_to_json(staged_metadata.join(&name), &metadata);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    files::swap_dir(&staged_metadata, &target)?;
+    Ok(())
+}
+
+#[test]
+fn metadata_follows_the_changes() {
+    let day = |day| {
+        DateTime::parse_from_rfc3339(&format!("2026-10-{day:02}T12:00:00Z"))
+            .unwrap()
+            .to_utc()
+    };
+    let event = EventEntry::example;
+
+    let first = [event("a", "1.01"), event("b", "1.01")];
+    let metadata = update(&[], &FileMetadata::new(), &first, day(1));
+    assert_eq!(metadata["a"].first_seen, day(1));
+
+    let second = [event("a", "1.01"), event("b", "2.02"), event("c", "1.01")];
+    let metadata = update(&first, &metadata, &second, day(2));
+    assert_eq!(
+        metadata["a"],
+        EventMetadata {
+            first_seen: day(1),
+            last_changed: day(1),
+        }
+    );
+    assert_eq!(
+        metadata["b"],
+        EventMetadata {
+            first_seen: day(1),
+            last_changed: day(2),
+        }
+    );
+    assert_eq!(metadata["c"].first_seen, day(2));
+}
+
+#[test]
+fn events_keep_their_metadata_when_they_get_an_uid() {
+    let day = |day| {
+        DateTime::parse_from_rfc3339(&format!("2026-10-{day:02}T12:00:00Z"))
+            .unwrap()
+            .to_utc()
+    };
+    let event = |uid| EventEntry::example(uid, "1.01");
+
+    let before = [event("")];
+    let metadata = update(&[], &FileMetadata::new(), &before, day(1));
+    let metadata = update(&before, &metadata, &[event("a")], day(2));
+    assert_eq!(
+        metadata["a"],
+        EventMetadata {
+            first_seen: day(1),
+            last_changed: day(1),
+        }
+    );
+}
+
+#[test]
+fn repeated_keys_are_numbered() {
+    let event = || EventEntry::example("a", "");
+    assert_eq!(keys(&[event(), event()], EventEntry::key), ["a", "a#2"]);
+}
+
+#[test]
+fn missing_metadata_is_replayed_from_the_history() -> anyhow::Result<()> {
+    use std::process::Command;
+
+    let dir = tempfile::tempdir()?;
+    let git = |args: &[&str], date: &str| {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(dir.path())
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    let commit = |events: &[EventEntry], date: &str| {
+        files::save_to_json(dir.path().join("informatik/a.json"), &events);
+        git(&["add", "."], date);
+        git(
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.org",
+                "commit",
+                "-q",
+                "--message",
+                "update",
+            ],
+            date,
+        );
+    };
+    let event = EventEntry::example;
+    git(&["init", "-q"], "2026-10-01T10:00:00Z");
+    fs::create_dir(dir.path().join("informatik"))?;
+    commit(&[event("x", "1.01")], "2026-10-01T10:00:00Z");
+    commit(
+        &[event("x", "2.02"), event("y", "1.01")],
+        "2026-10-03T10:00:00Z",
+    );
+
+    let repo = events_git::open(&crate::config::Git::default(), dir.path());
+    let names = BTreeSet::from(["a.json".to_owned()]);
+    let now = DateTime::parse_from_rfc3339("2026-10-05T12:00:00Z")?.to_utc();
+    let metadata = &from_history(&*repo, "informatik", &names, now)?["a.json"];
+    let end_of_day =
+        |day| history::end_of_day(chrono::NaiveDate::from_ymd_opt(2026, 10, day).unwrap());
+    assert_eq!(
+        metadata["x"],
+        EventMetadata {
+            first_seen: end_of_day(1)?,
+            last_changed: end_of_day(3)?,
+        }
+    );
+    assert_eq!(metadata["y"].first_seen, end_of_day(3)?);
+    Ok(())
+}
@@ -0,0 +1,255 @@
+//! ICS exports behind the myHAW login
+
+use std::env;
+
+use anyhow::Context as _;
+use scraper::{ElementRef, Html, Selector};
+use url::Url;
+
+use crate::http;
+use crate::ics_urls::{self, Link};
+
+const DEFAULT_LOGIN_URL: &str = "https://myhaw.haw-hamburg.de/";
+
+/// Login pages and single sign on redirects until the login is done
+const MAX_LOGIN_STEPS: usize = 5;
+
+pub struct MyHaw {
+    login_url: Url,
+    export_urls: Vec<Url>,
+    username: String,
+    password: String,
+}
+
+impl MyHaw {
+    /// Credentials are taken from `MYHAW_USERNAME` and `MYHAW_PASSWORD`
+    pub fn new(login_url: Option<&Url>, export_urls: &[Url]) -> anyhow::Result<Self> {
+        let username = env::var("MYHAW_USERNAME").context("MYHAW_USERNAME")?;
+        let password = env::var("MYHAW_PASSWORD").context("MYHAW_PASSWORD")?;
+        let login_url =
+            login_url.map_or_else(|| Url::parse(DEFAULT_LOGIN_URL), |url| Ok(url.clone()))?;
+        Ok(Self {
+            login_url,
+            export_urls: export_urls.to_vec(),
+            username,
+            password,
+        })
+    }
+
+    /// Login and collect the ICS urls from the export pages.
+    ///
+    /// The session cookies are kept by the shared agent of [`http`] so the ICS urls can be downloaded like public ones.
+    pub fn get_ics_urls(&self) -> anyhow::Result<Vec<Link>> {
+        self.login().context("myHAW login")?;
+        let mut result = Vec::new();
+        for export_url in &self.export_urls {
+            let (url, body) = http::get_page(export_url.as_str())?;
+            anyhow::ensure!(
+                LoginForm::find(&url, &body).is_none(),
+                "export page {export_url} requires a login"
+            );
+            result.append(&mut ics_urls::get_from_body(&url, &body));
+        }
+        anyhow::ensure!(!result.is_empty(), "no ics urls found");
+        Ok(result)
+    }
+
+    fn login(&self) -> anyhow::Result<()> {
+        let (mut url, mut body) = http::get_page(self.login_url.as_str())?;
+        for _ in 0..MAX_LOGIN_STEPS {
+            let Some(form) = LoginForm::find(&url, &body) else {
+                return Ok(());
+            };
+            let has_password = form.has_password;
+            let (action, fields) = form.into_fields(&self.username, &self.password);
+            (url, body) = http::post_form(url.join(&action)?.as_str(), &fields)?;
+            if has_password {
+                anyhow::ensure!(
+                    LoginForm::find(&url, &body).is_none_or(|form| !form.has_password),
+                    "credentials were rejected"
+                );
+            }
+        }
+        anyhow::bail!("not finished after {MAX_LOGIN_STEPS} steps")
+    }
+}
+
+/// A form which needs to be submitted in order to log in.
+///
+/// Either it asks for the credentials or it relays a single sign on assertion.
+struct LoginForm {
+    action: String,
+    has_password: bool,
+    fields: Vec<Field>,
+}
+
+enum Field {
+    Username(String),
+    Password(String),
+    Value(String, String),
+}
+
+impl LoginForm {
+    fn find(page_url: &Url, body: &str) -> Option<Self> {
+        let html = Html::parse_document(body);
+        let form_selector = Selector::parse("form").unwrap();
+        html.select(&form_selector)
+            .find_map(|form| Self::parse(page_url, form))
+    }
+
+    fn parse(page_url: &Url, form: ElementRef) -> Option<Self> {
+        let input_selector = Selector::parse("input, button").unwrap();
+        let mut has_password = false;
+        let mut is_sso_relay = false;
+        let mut has_username = false;
+        let mut has_submit = false;
+        let mut fields = Vec::new();
+        for input in form.select(&input_selector) {
+            let input = input.value();
+            let Some(name) = input.attr("name") else {
+                continue;
+            };
+            let value = input.attr("value").unwrap_or_default().to_owned();
+            let default_kind = if input.name() == "button" {
+                "submit"
+            } else {
+                "text"
+            };
+            let kind = input
+                .attr("type")
+                .unwrap_or(default_kind)
+                .to_ascii_lowercase();
+            match kind.as_str() {
+                "password" => {
+                    has_password = true;
+                    fields.push(Field::Password(name.to_owned()));
+                }
+                "text" | "email" if !has_username => {
+                    has_username = true;
+                    fields.push(Field::Username(name.to_owned()));
+                }
+                "checkbox" | "radio" if input.attr("checked").is_none() => {}
+                "submit" | "image" if !has_submit => {
+                    has_submit = true;
+                    fields.push(Field::Value(name.to_owned(), value));
+                }
+                "submit" | "image" | "button" | "reset" => {}
+                _ => {
+                    is_sso_relay |= matches!(name, "SAMLResponse" | "SAMLRequest");
+                    fields.push(Field::Value(name.to_owned(), value));
+                }
+            }
+        }
+        if !has_password && !is_sso_relay {
+            return None;
+        }
+        let action = form
+            .value()
+            .attr("action")
+            .filter(|action| !action.is_empty())
+            .map_or_else(|| page_url.to_string(), ToOwned::to_owned);
+        Some(Self {
+            action,
+            has_password,
+            fields,
+        })
+    }
+
+    /// Returns the action and the filled in fields
+    fn into_fields(self, username: &str, password: &str) -> (String, Vec<(String, String)>) {
+        let fields = self
+            .fields
+            .into_iter()
+            .map(|field| match field {
+                Field::Username(name) if self.has_password => (name, username.to_owned()),
+                Field::Username(name) => (name, String::new()),
+                Field::Password(name) => (name, password.to_owned()),
+                Field::Value(name, value) => (name, value),
+            })
+            .collect();
+        (self.action, fields)
+    }
+}
+
+#[cfg(test)]
+fn stub_server() -> Url {
+    use crate::test_server::{Request, Response};
+
+    const SESSION: &str = "session=stub-session";
+
+    fn handle(request: &Request) -> Response {
+        let logged_in = request
+            .header("Cookie")
+            .is_some_and(|cookie| cookie.contains(SESSION));
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/login") => Response::ok(
+                r#"<form method="post" action="/sso"><input type="hidden" name="csrf" value="token&amp;1"><input name="user"><input type="password" name="pass"><button name="_eventId_proceed">Login</button></form>"#,
+            ),
+            ("POST", "/sso")
+                if request.body == "csrf=token%261&user=alice&pass=secret&_eventId_proceed=" =>
+            {
+                Response::ok(
+                    r#"<form method="post" action="/login/callback"><input type="hidden" name="SAMLResponse" value="assertion"></form>"#,
+                )
+            }
+            ("POST", "/sso") => Response::ok(
+                r#"<p>Wrong password</p><form method="post" action="/sso"><input name="user"><input type="password" name="pass"></form>"#,
+            ),
+            ("POST", "/login/callback") if request.body == "SAMLResponse=assertion" => {
+                Response::redirect("/home").header("Set-Cookie", &format!("{SESSION}; Path=/"))
+            }
+            ("GET", "/home") if logged_in => {
+                Response::ok(r#"<form action="/search"><input name="q"></form>"#)
+            }
+            ("GET", "/exports") if logged_in => Response::ok(
+                r#"<a href="/export/personal.ics">personal</a> <a href="/export/published.ics">published</a>"#,
+            ),
+            ("GET", "/export/personal.ics") if logged_in => {
+                Response::ok("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n")
+            }
+            ("GET", "/exports" | "/export/personal.ics") => Response::redirect("/login"),
+            _ => Response::status(404),
+        }
+    }
+
+    crate::test_server::start(handle)
+}
+
+#[cfg(test)]
+fn stub_myhaw(server: &Url, password: &str) -> MyHaw {
+    MyHaw {
+        login_url: server.join("login").unwrap(),
+        export_urls: vec![server.join("exports").unwrap()],
+        username: "alice".to_owned(),
+        password: password.to_owned(),
+    }
+}
+
+#[test]
+fn login_and_download_exports() -> anyhow::Result<()> {
+    let server = stub_server();
+    let links = stub_myhaw(&server, "secret").get_ics_urls()?;
+    assert_eq!(
+        links,
+        [
+            Link {
+                url: server.join("export/personal.ics")?,
+                text: "personal".to_owned(),
+            },
+            Link {
+                url: server.join("export/published.ics")?,
+                text: "published".to_owned(),
+            },
+        ]
+    );
+    let ics = http::get_bytes(links[0].url.as_str())?;
+    assert_eq!(ics, b"BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n");
+    Ok(())
+}
+
+#[test]
+fn login_with_wrong_password_fails() {
+    let server = stub_server();
+    let err = stub_myhaw(&server, "wrong").get_ics_urls().unwrap_err();
+    assert_eq!(format!("{err:#}"), "myHAW login: credentials were rejected");
+}
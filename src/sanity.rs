@@ -0,0 +1,127 @@
+//! Expectations per source to keep the previous base when a run looks partially broken
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Sanity {
+    /// Minimum amount of discovered ICS urls
+    #[serde(default = "default_min_urls")]
+    pub min_urls: usize,
+    /// Minimum share of the discovered ICS urls which have to be downloaded successfully
+    #[serde(default)]
+    pub min_success_ratio: f64,
+    /// Maximum share of the ICS urls of the last run which may vanish
+    #[serde(default = "default_max_drop")]
+    pub max_drop: f64,
+}
+
+const fn default_min_urls() -> usize {
+    1
+}
+
+const fn default_max_drop() -> f64 {
+    1.0
+}
+
+impl Default for Sanity {
+    fn default() -> Self {
+        Self {
+            min_urls: default_min_urls(),
+            min_success_ratio: 0.0,
+            max_drop: default_max_drop(),
+        }
+    }
+}
+
+#[expect(clippy::cast_precision_loss, reason = "url amounts are small")]
+fn ratio(part: usize, total: usize) -> f64 {
+    part as f64 / total as f64
+}
+
+impl Sanity {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("min-success-ratio", self.min_success_ratio),
+            ("max-drop", self.max_drop),
+        ] {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&value),
+                "sanity {name} should be between 0 and 1"
+            );
+        }
+        Ok(())
+    }
+
+    /// Check the discovered urls against the ones of the last run before downloading anything
+    pub fn check_discovery(
+        &self,
+        urls: usize,
+        previous: usize,
+        vanished: usize,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            urls >= self.min_urls,
+            "discovered {urls} urls but expected at least {}",
+            self.min_urls
+        );
+        if previous > 0 {
+            let drop = ratio(vanished, previous);
+            anyhow::ensure!(
+                drop <= self.max_drop,
+                "{vanished} of {previous} urls of the last run vanished ({:.0}% > {:.0}%)",
+                drop * 100.0,
+                self.max_drop * 100.0
+            );
+        }
+        Ok(())
+    }
+
+    /// Check how many of the discovered urls were downloaded successfully
+    pub fn check_downloads(&self, successful: usize, total: usize) -> anyhow::Result<()> {
+        if total == 0 {
+            return Ok(());
+        }
+        let success = ratio(successful, total);
+        anyhow::ensure!(
+            success >= self.min_success_ratio,
+            "only {successful} of {total} urls were downloaded ({:.0}% < {:.0}%)",
+            success * 100.0,
+            self.min_success_ratio * 100.0
+        );
+        Ok(())
+    }
+}
+
+#[test]
+fn discovery_checks() {
+    let sanity = Sanity {
+        min_urls: 2,
+        min_success_ratio: 0.0,
+        max_drop: 0.5,
+    };
+    assert!(sanity.check_discovery(10, 10, 5).is_ok());
+    assert!(sanity.check_discovery(2, 0, 0).is_ok());
+    let err = sanity.check_discovery(1, 0, 0).unwrap_err();
+    assert_eq!(err.to_string(), "discovered 1 urls but expected at least 2");
+    let err = sanity.check_discovery(10, 10, 6).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "6 of 10 urls of the last run vanished (60% > 50%)"
+    );
+}
+
+#[test]
+fn download_checks() {
+    let sanity = Sanity {
+        min_success_ratio: 0.8,
+        ..Sanity::default()
+    };
+    assert!(sanity.check_downloads(8, 10).is_ok());
+    assert!(sanity.check_downloads(0, 0).is_ok());
+    let err = sanity.check_downloads(7, 10).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "only 7 of 10 urls were downloaded (70% < 80%)"
+    );
+}
@@ -0,0 +1,253 @@
+//! JSON Schemas of the generated files. They are shipped next to the files and used to validate existing trees.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path};
+
+use anyhow::Context as _;
+use schemars::{Schema, schema_for};
+
+use crate::event_entry::EventEntryV4;
+use crate::{EventEntry, events_git, files, metadata, v4, v5};
+
+/// Within the events folder
+pub const EVENTS_FILE: &str = "events.schema.json";
+/// Within the events folder
+pub const METADATA_FILE: &str = "metadata.schema.json";
+/// Within the eventfiles folder. v4 removes all json files directly in there which it did not generate.
+const EVENTFILES_FOLDER: &str = "schema";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Format {
+    Events,
+    Metadata,
+    V4,
+    V5,
+}
+
+impl Format {
+    const ALL: [Self; 4] = [Self::Events, Self::Metadata, Self::V4, Self::V5];
+
+    fn schema(self) -> Schema {
+        let mut schema = match self {
+            Self::Events => schema_for!(Vec<EventEntry>),
+            Self::Metadata => schema_for!(metadata::FileMetadata),
+            Self::V4 => schema_for!(Vec<EventEntryV4>),
+            Self::V5 => schema_for!(v5::Envelope),
+        };
+        schema.insert("title".to_owned(), self.title().into());
+        schema
+    }
+
+    const fn title(self) -> &'static str {
+        match self {
+            Self::Events => "Eventfile",
+            Self::Metadata => "Event metadata",
+            Self::V4 => "Eventfile v4",
+            Self::V5 => "Eventfile v5",
+        }
+    }
+
+    /// Formats like date-time are only annotations by default, they are asserted here
+    fn validator(self) -> anyhow::Result<jsonschema::Validator> {
+        jsonschema::options()
+            .should_validate_formats(true)
+            .build(self.schema().as_value())
+            .map_err(|err| anyhow::anyhow!("{self:?} schema is invalid: {err}"))
+    }
+
+    /// Path of the schema relative to the working directory
+    fn path(self) -> String {
+        match self {
+            Self::Events => format!("{}/{EVENTS_FILE}", events_git::FOLDER),
+            Self::Metadata => format!("{}/{METADATA_FILE}", events_git::FOLDER),
+            Self::V4 => format!("{}/{EVENTFILES_FOLDER}/v4.schema.json", v4::FOLDER),
+            Self::V5 => format!("{}/{EVENTFILES_FOLDER}/v5.schema.json", v4::FOLDER),
+        }
+    }
+
+    /// Format of a file by its path relative to the working directory. Other files like the health are not checked.
+    fn of(path: &Path) -> Option<Self> {
+        if path.extension().is_none_or(|extension| extension != "json") {
+            return None;
+        }
+        let parts = path
+            .components()
+            .map(|component| match component {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        match parts.as_slice() {
+            [events, folder, _, _]
+                if *events == events_git::FOLDER && *folder == metadata::FOLDER =>
+            {
+                Some(Self::Metadata)
+            }
+            [events, base, _]
+                if *events == events_git::FOLDER
+                    && *base != metadata::FOLDER
+                    && !base.starts_with('.') =>
+            {
+                Some(Self::Events)
+            }
+            [eventfiles, folder, _] if Path::new(eventfiles).join(folder) == v5::folder() => {
+                Some(Self::V5)
+            }
+            [eventfiles, _] if *eventfiles == v4::FOLDER => Some(Self::V4),
+            _ => None,
+        }
+    }
+}
+
+fn write(format: Format) {
+    let path = format.path();
+    let path = Path::new(&path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("should be able to create the schema folder");
+    }
+    files::save_to_json(path, &format.schema());
+}
+
+/// Write the schemas of the events folder and return their paths within it
+pub fn save_events() -> Vec<String> {
+    write(Format::Events);
+    write(Format::Metadata);
+    vec![EVENTS_FILE.to_owned(), METADATA_FILE.to_owned()]
+}
+
+/// Write the schemas of the eventfiles folder
+pub fn save_eventfiles() {
+    write(Format::V4);
+    write(Format::V5);
+}
+
+/// Validate the events and eventfiles folders within the root against the schemas.
+///
+/// Returns the problems by file. Files without a known format are skipped.
+pub fn validate(root: &Path) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+    let validators = Format::ALL
+        .into_iter()
+        .map(|format| Ok((format, format.validator()?)))
+        .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+
+    let mut problems = BTreeMap::new();
+    let mut checked = 0;
+    for folder in [events_git::FOLDER, v4::FOLDER] {
+        for relative in files::list_files(&root.join(folder))? {
+            let relative = Path::new(folder).join(relative);
+            let Some(format) = Format::of(&relative) else {
+                continue;
+            };
+            checked += 1;
+            let path = root.join(&relative);
+            let content = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+            let errors = match serde_json::from_slice::<serde_json::Value>(&content) {
+                Ok(instance) => validators[&format]
+                    .iter_errors(&instance)
+                    .map(|err| format!("{}: {err}", err.instance_path()))
+                    .collect::<Vec<_>>(),
+                Err(err) => vec![format!("invalid JSON: {err}")],
+            };
+            if !errors.is_empty() {
+                problems.insert(relative.display().to_string(), errors);
+            }
+        }
+    }
+    println!("Validated {checked} files");
+    Ok(problems)
+}
+
+#[test]
+fn schemas_accept_generated_files() -> anyhow::Result<()> {
+    let event = EventEntry::example("a", "1.01");
+    let events = serde_json::to_value([&event])?;
+    let validator = Format::Events.validator()?;
+    assert!(validator.is_valid(&events));
+    assert!(!validator.is_valid(&serde_json::json!([{ "name": "BAI1-SE1" }])));
+
+    let v4 = serde_json::to_value([EventEntryV4::from(event)])?;
+    let validator = Format::V4.validator()?;
+    assert!(validator.is_valid(&v4));
+    assert!(!validator.is_valid(&events));
+    let mut wrong_time = v4;
+    wrong_time[0]["StartTime"] = "2026-10-20 08:00".into();
+    assert!(!validator.is_valid(&wrong_time));
+
+    let envelope = serde_json::to_value(v5::Envelope {
+        schema_version: v5::SCHEMA_VERSION,
+        generated_at: chrono::DateTime::UNIX_EPOCH,
+        sources: vec![v5::SourceFile {
+            base: "informatik".to_owned(),
+            file: "BAI1-SE1.json".to_owned(),
+        }],
+        events: vec![crate::event_entry::EventEntryV5::try_from(
+            crate::event_files::SourcedEvent {
+                base: "informatik".to_owned(),
+                file: "BAI1-SE1.json".to_owned(),
+                event: EventEntry::example("a", "1.01"),
+            },
+        )?],
+    })?;
+    let validator = Format::V5.validator()?;
+    assert!(validator.is_valid(&envelope));
+    let mut without_events = envelope;
+    without_events
+        .as_object_mut()
+        .expect("envelope is an object")
+        .remove("events");
+    assert!(!validator.is_valid(&without_events));
+
+    let metadata = serde_json::to_value(metadata::FileMetadata::from([(
+        "a".to_owned(),
+        metadata::EventMetadata {
+            first_seen: chrono::DateTime::UNIX_EPOCH,
+            last_changed: chrono::DateTime::UNIX_EPOCH,
+        },
+    )]))?;
+    let validator = Format::Metadata.validator()?;
+    assert!(validator.is_valid(&metadata));
+    let mut wrong_time = metadata;
+    wrong_time["a"]["lastChanged"] = "yesterday".into();
+    assert!(!validator.is_valid(&wrong_time));
+    Ok(())
+}
+
+#[test]
+fn formats_by_path() {
+    let of = |path: &str| Format::of(Path::new(path));
+    assert_eq!(of("events/informatik/BAI1-SE1.json"), Some(Format::Events));
+    assert_eq!(
+        of("events/metadata/informatik/BAI1-SE1.json"),
+        Some(Format::Metadata)
+    );
+    assert_eq!(of("eventfiles/BAI1-SE1.json"), Some(Format::V4));
+    assert_eq!(of("eventfiles/v5/BAI1-SE1.json"), Some(Format::V5));
+    assert_eq!(of("events/health.json"), None);
+    assert_eq!(of("events/metadata/BAI1-SE1.json"), None);
+    assert_eq!(of("events/.github/ci.json"), None);
+    assert_eq!(of("eventfiles/schema/v4.schema.json"), None);
+    assert_eq!(of("events/informatik/README.md"), None);
+}
+
+#[test]
+fn validate_reports_problems_by_file() -> anyhow::Result<()> {
+    let root = tempfile::tempdir()?;
+    let base = root.path().join(events_git::FOLDER).join("informatik");
+    fs::create_dir_all(&base)?;
+    fs::create_dir_all(root.path().join(v4::FOLDER))?;
+    fs::write(
+        base.join("good.json"),
+        r#"[{"name":"a","location":"","description":"","start":"2026-10-20T08:00:00","end":"2026-10-20T09:30:00"}]"#,
+    )?;
+    fs::write(base.join("bad.json"), r#"[{"name":"a"}]"#)?;
+    fs::write(root.path().join(v4::FOLDER).join("broken.json"), "[")?;
+
+    let problems = validate(root.path())?;
+    assert_eq!(
+        problems.keys().collect::<Vec<_>>(),
+        ["eventfiles/broken.json", "events/informatik/bad.json"]
+    );
+    assert!(problems["eventfiles/broken.json"][0].starts_with("invalid JSON"));
+    Ok(())
+}
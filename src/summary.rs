@@ -0,0 +1,207 @@
+//! Summary of a run which is used as the commit message of the events repo
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context as _;
+
+use crate::files;
+
+/// Subjects name the bases directly up to this amount
+const MAX_SUBJECT_BASES: usize = 3;
+
+/// File names of a base which differ from the committed version
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct BaseChanges {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+    pub event_delta: isize,
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Bases with changed files
+    pub changes: BTreeMap<String, BaseChanges>,
+    /// Bases which were kept untouched and the reason
+    pub failed: BTreeMap<String, String>,
+}
+
+fn event_amount(content: &[u8]) -> anyhow::Result<isize> {
+    let events = serde_json::from_slice::<Vec<serde::de::IgnoredAny>>(content)?;
+    Ok(isize::try_from(events.len())?)
+}
+
+impl BaseChanges {
+    /// Compare the committed files of a base with the staged ones. Only differing files are parsed.
+    pub fn compare(committed: &Path, staged: &Path) -> anyhow::Result<Self> {
+        let before = files::json_files(committed)?;
+        let after = files::json_files(staged)?;
+        let mut changes = Self::default();
+        for name in before.union(&after) {
+            let read = |dir: &Path| {
+                let path = dir.join(name);
+                fs::read(&path).with_context(|| format!("read {}", path.display()))
+            };
+            match (before.contains(name), after.contains(name)) {
+                (true, true) => {
+                    let old = read(committed)?;
+                    let new = read(staged)?;
+                    if old != new {
+                        changes.changed.push(name.clone());
+                        changes.event_delta += event_amount(&new)? - event_amount(&old)?;
+                    }
+                }
+                (false, _) => {
+                    changes.added.push(name.clone());
+                    changes.event_delta += event_amount(&read(staged)?)?;
+                }
+                (true, false) => {
+                    changes.removed.push(name.clone());
+                    changes.event_delta -= event_amount(&read(committed)?)?;
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Summary {
+    /// Record the changes of a base when there are any
+    pub fn insert(&mut self, base: &str, changes: BaseChanges) {
+        if !changes.is_empty() {
+            self.changes.insert(base.to_owned(), changes);
+        }
+    }
+
+    /// Short subject line followed by the changed files per base and the failed sources.
+    ///
+    /// Files are prefixed like `git status --short` does: A for added, M for changed and D for removed.
+    pub fn commit_message(&self) -> String {
+        let mut subject = if self.changes.is_empty() {
+            "Update metadata".to_owned()
+        } else if self.changes.len() <= MAX_SUBJECT_BASES {
+            let bases = self.changes.keys().map(String::as_str);
+            format!("Update {}", bases.collect::<Vec<_>>().join(", "))
+        } else {
+            format!("Update {} bases", self.changes.len())
+        };
+        if !self.failed.is_empty() {
+            _ = write!(subject, " ({} failed)", self.failed.len());
+        }
+
+        let mut body = String::new();
+        for (base, changes) in &self.changes {
+            _ = writeln!(
+                body,
+                "{base}: {} added, {} changed, {} removed files, {:+} events",
+                changes.added.len(),
+                changes.changed.len(),
+                changes.removed.len(),
+                changes.event_delta
+            );
+            for (status, names) in [
+                ('A', &changes.added),
+                ('M', &changes.changed),
+                ('D', &changes.removed),
+            ] {
+                for name in names {
+                    _ = writeln!(body, "  {status} {name}");
+                }
+            }
+            body.push('\n');
+        }
+        if !self.failed.is_empty() {
+            body.push_str("Failed sources kept untouched:\n");
+            for (base, reason) in &self.failed {
+                _ = writeln!(body, "{base}: {reason}");
+            }
+        }
+
+        if body.is_empty() {
+            subject
+        } else {
+            format!("{subject}\n\n{}", body.trim_end())
+        }
+    }
+}
+
+#[test]
+fn compare_counts_files_and_events() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let committed = dir.path().join("committed");
+    let staged = dir.path().join("staged");
+    fs::create_dir(&committed)?;
+    fs::create_dir(&staged)?;
+    fs::write(committed.join("same.json"), "[{}]")?;
+    fs::write(staged.join("same.json"), "[{}]")?;
+    fs::write(committed.join("changed.json"), "[{}]")?;
+    fs::write(staged.join("changed.json"), "[{}, {}, {}]")?;
+    fs::write(committed.join("removed.json"), "[{}, {}, {}, {}]")?;
+    fs::write(staged.join("added.json"), "[{}]")?;
+
+    assert_eq!(
+        BaseChanges::compare(&committed, &staged)?,
+        BaseChanges {
+            added: vec!["added.json".to_owned()],
+            changed: vec!["changed.json".to_owned()],
+            removed: vec!["removed.json".to_owned()],
+            event_delta: -1,
+        }
+    );
+    let missing = dir.path().join("missing");
+    assert_eq!(BaseChanges::compare(&missing, &staged)?.added.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn commit_message_lists_changes_and_failures() {
+    let mut summary = Summary::default();
+    assert_eq!(summary.commit_message(), "Update metadata");
+
+    summary.insert("unchanged", BaseChanges::default());
+    summary.insert(
+        "informatik",
+        BaseChanges {
+            added: vec!["new.json".to_owned()],
+            changed: vec!["a.json".to_owned(), "b.json".to_owned()],
+            removed: Vec::new(),
+            event_delta: 12,
+        },
+    );
+    summary
+        .failed
+        .insert("mt".to_owned(), "no ics urls found".to_owned());
+    assert_eq!(
+        summary.commit_message(),
+        "Update informatik (1 failed)\n\n\
+         informatik: 1 added, 2 changed, 0 removed files, +12 events\n\
+         \x20 A new.json\n\
+         \x20 M a.json\n\
+         \x20 M b.json\n\n\
+         Failed sources kept untouched:\n\
+         mt: no ics urls found"
+    );
+
+    for base in ["a", "b", "c"] {
+        summary.insert(
+            base,
+            BaseChanges {
+                removed: vec!["old.json".to_owned()],
+                event_delta: -3,
+                ..BaseChanges::default()
+            },
+        );
+    }
+    assert!(
+        summary
+            .commit_message()
+            .starts_with("Update 4 bases (1 failed)\n\n")
+    );
+}
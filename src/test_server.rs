@@ -0,0 +1,123 @@
+//! Minimal HTTP server to run tests against stubs of remote services
+
+use std::io::{BufRead as _, BufReader, Read as _, Write as _};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use url::Url;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<String>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    pub fn redirect(location: &str) -> Self {
+        Self::status(302).header("Location", location)
+    }
+
+    pub const fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Serve the handler on a random local port until the test process ends
+pub fn start<F>(handler: F) -> Url
+where
+    F: Fn(&Request) -> Response + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind test server");
+    let address = listener.local_addr().expect("test server address");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.expect("accept test connection");
+            let request = read_request(&stream);
+            let response = handler(&request);
+            write_response(&mut stream, &response);
+        }
+    });
+    Url::parse(&format!("http://{address}/")).unwrap()
+}
+
+fn read_request(stream: &TcpStream) -> Request {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).expect("read request line");
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let path = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).expect("read header line");
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: String::new(),
+    };
+    let length = request
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).expect("read request body");
+    request.body = String::from_utf8(body).expect("request body should be UTF-8");
+    request
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) {
+    let mut head = Vec::new();
+    writeln!(head, "HTTP/1.1 {} Stub\r", response.status).unwrap();
+    for (name, value) in &response.headers {
+        writeln!(head, "{name}: {value}\r").unwrap();
+    }
+    writeln!(head, "Content-Length: {}\r", response.body.len()).unwrap();
+    writeln!(head, "Connection: close\r\n\r").unwrap();
+    stream
+        .write_all(&head)
+        .and_then(|()| stream.write_all(response.body.as_bytes()))
+        .expect("write test response");
+}
@@ -6,61 +6,24 @@ use std::fs;
 use std::path::Path;
 
 use crate::event_entry::EventEntryV4;
-use crate::{EventEntry, events_git};
+use crate::{EventEntry, event_files, files};
 
 enum HasChanged {
     Changed,
     Unchanged,
 }
 
-const FOLDER: &str = "eventfiles";
+pub const FOLDER: &str = "eventfiles";
 pub fn update() {
-    events_git::checkout();
-    let events = read_all_events().expect("Should be able to read eventfiles");
+    let events = event_files::read_all()
+        .expect("Should be able to read eventfiles")
+        .into_iter()
+        .map(|sourced| sourced.event)
+        .collect();
     fs::create_dir_all(FOLDER).expect("should be able to create the eventfiles folder");
     save_events(events);
 }
 
-fn read_all_events() -> anyhow::Result<Vec<EventEntry>> {
-    let mut result = Vec::new();
-    for entry in fs::read_dir(events_git::FOLDER)? {
-        let entry = entry?;
-        if !entry.file_type()?.is_dir() {
-            continue;
-        }
-        let file_name = entry.file_name();
-        let file_name = file_name
-            .to_str()
-            .expect("events should only contain unicode filenames");
-        if file_name.starts_with('.') {
-            continue;
-        }
-        result.append(&mut read_events_from_base(&entry.path())?);
-    }
-    Ok(result)
-}
-
-fn read_events_from_base(base_path: &Path) -> anyhow::Result<Vec<EventEntry>> {
-    let mut result = Vec::new();
-    for entry in fs::read_dir(base_path)? {
-        let path = entry?.path();
-        if !path.is_file() {
-            continue;
-        }
-        let is_json = path
-            .extension()
-            .and_then(|extension| extension.to_str())
-            .is_some_and(|extension| extension == "json");
-        if !is_json {
-            continue;
-        }
-        let content = fs::read_to_string(path)?;
-        let mut events = serde_json::from_str(&content)?;
-        result.append(&mut events);
-    }
-    Ok(result)
-}
-
 fn save_events(all: Vec<EventEntry>) {
     let grouped = get_grouped(all);
     println!("Events by name: {}", grouped.len());
@@ -108,8 +71,10 @@ fn get_grouped(all: Vec<EventEntry>) -> HashMap<String, Vec<EventEntry>> {
 
     let mut grouped: HashMap<String, Vec<EventEntry>> = HashMap::new();
     for entry in all {
-        let filename = entry.name.replace('/', "-");
-        grouped.entry(filename).or_default().push(entry);
+        match files::sanitize_name(&entry.name) {
+            Ok(filename) => grouped.entry(filename).or_default().push(entry),
+            Err(err) => println!("WARNING: skip event {err:#}"),
+        }
     }
 
     #[expect(clippy::iter_over_hash_type)]
@@ -121,7 +86,11 @@ fn get_grouped(all: Vec<EventEntry>) -> HashMap<String, Vec<EventEntry>> {
                 .or_else(|| ne(&a.description, &b.description))
                 .unwrap_or_else(|| a.name.cmp(&b.name))
         });
-        groupvalues.dedup();
+        // v4 has no UIDs so the same event of different ICS files is only listed once
+        groupvalues.dedup_by(|a, b| {
+            (&a.name, &a.location, &a.description, a.start, a.end)
+                == (&b.name, &b.location, &b.description, b.start, b.end)
+        });
     }
 
     grouped
@@ -173,3 +142,10 @@ fn cleanup_superfluous_eventfiles(expected_files: &[String]) -> Vec<String> {
     }
     removed
 }
+
+#[test]
+fn events_differing_only_in_uid_are_merged() {
+    let event = |uid| EventEntry::example(uid, "1.01");
+    let grouped = get_grouped(vec![event("a"), event(""), event("b")]);
+    assert_eq!(grouped["BAI1-SE1"].len(), 1);
+}
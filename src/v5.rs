@@ -0,0 +1,171 @@
+//! v5 eventfiles: every file is an envelope with provenance around events with structured fields.
+//!
+//! Generated alongside v4 into a subfolder of its eventfiles folder so consumers can migrate gradually.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::event_entry::EventEntryV5;
+use crate::{event_files, files, v4};
+
+pub const SCHEMA_VERSION: u32 = 5;
+
+/// v5 eventfile with the events of one name
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub schema_version: u32,
+    /// When the content of the file last changed
+    pub generated_at: DateTime<Utc>,
+    pub sources: Vec<SourceFile>,
+    pub events: Vec<EventEntryV5>,
+}
+
+/// Eventfile of a base which contributed events
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SourceFile {
+    pub base: String,
+    pub file: String,
+}
+
+pub fn folder() -> PathBuf {
+    Path::new(v4::FOLDER).join("v5")
+}
+
+pub fn update(now: DateTime<Utc>) {
+    let folder = folder();
+    fs::create_dir_all(&folder).expect("should be able to create the v5 eventfiles folder");
+    let grouped = get_grouped().expect("Should be able to read eventfiles");
+
+    let mut changed = 0;
+    for (name, events) in &grouped {
+        let path = folder.join(format!("{name}.json"));
+        if write_when_different(&path, events, now).expect("write v5 event file") {
+            changed += 1;
+        }
+    }
+    println!("v5 eventfiles: {} names, {changed} changed", grouped.len());
+
+    let existing = files::json_files(&folder).expect("should be able to read v5 eventfiles");
+    for file in existing {
+        let is_expected = file
+            .strip_suffix(".json")
+            .is_some_and(|name| grouped.contains_key(name));
+        if !is_expected {
+            fs::remove_file(folder.join(&file)).expect("remove superfluous v5 event file");
+        }
+    }
+}
+
+/// Events grouped by their file name like v4 does
+fn get_grouped() -> anyhow::Result<BTreeMap<String, Vec<EventEntryV5>>> {
+    let mut grouped = BTreeMap::<String, Vec<EventEntryV5>>::new();
+    for sourced in event_files::read_all()? {
+        let filename = match files::sanitize_name(&sourced.event.name) {
+            Ok(filename) => filename,
+            Err(err) => {
+                println!("WARNING: skip event {err:#}");
+                continue;
+            }
+        };
+        match EventEntryV5::try_from(sourced) {
+            Ok(event) => grouped.entry(filename).or_default().push(event),
+            Err(err) => println!("WARNING: skip event {err:#}"),
+        }
+    }
+    for events in grouped.values_mut() {
+        events.sort_by(|first, second| {
+            (
+                first.start,
+                first.end,
+                &first.rooms,
+                &first.base,
+                &first.source_file,
+            )
+                .cmp(&(
+                    second.start,
+                    second.end,
+                    &second.rooms,
+                    &second.base,
+                    &second.source_file,
+                ))
+        });
+        events.dedup();
+    }
+    Ok(grouped)
+}
+
+/// Keep the existing file including its generation time when neither the sources nor the events changed
+fn write_when_different(
+    path: &Path,
+    events: &[EventEntryV5],
+    now: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+    let sources = events
+        .iter()
+        .map(|event| SourceFile {
+            base: event.base.clone(),
+            file: event.source_file.clone(),
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let existing = fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Envelope>(&content).ok());
+    if existing.is_some_and(|existing| {
+        existing.schema_version == SCHEMA_VERSION
+            && existing.sources == sources
+            && existing.events == events
+    }) {
+        return Ok(false);
+    }
+    let envelope = Envelope {
+        schema_version: SCHEMA_VERSION,
+        generated_at: now,
+        sources,
+        events: events.to_vec(),
+    };
+    fs::write(path, files::to_json(&envelope))?;
+    Ok(true)
+}
+
+#[test]
+fn unchanged_files_keep_their_generation_time() -> anyhow::Result<()> {
+    use crate::EventEntry;
+    use crate::event_files::SourcedEvent;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("BAI1-SE1.json");
+    let event = |location| {
+        EventEntryV5::try_from(SourcedEvent {
+            base: "informatik".to_owned(),
+            file: "BAI1-SE1.json".to_owned(),
+            event: EventEntry::example("a", location),
+        })
+    };
+    let first = DateTime::UNIX_EPOCH;
+    let second = first + chrono::Duration::days(1);
+
+    assert!(write_when_different(&path, &[event("1.01")?], first)?);
+    assert!(!write_when_different(&path, &[event("1.01")?], second)?);
+    let envelope: Envelope = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    assert_eq!(envelope.generated_at, first);
+    assert_eq!(
+        envelope.sources,
+        [SourceFile {
+            base: "informatik".to_owned(),
+            file: "BAI1-SE1.json".to_owned(),
+        }]
+    );
+
+    assert!(write_when_different(&path, &[event("2.02")?], second)?);
+    let envelope: Envelope = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    assert_eq!(envelope.generated_at, second);
+    Ok(())
+}